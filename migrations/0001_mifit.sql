@@ -0,0 +1,13 @@
+-- measurements, the columns of the original hand-made table
+CREATE TABLE IF NOT EXISTS mifit (
+    time timestamptz NOT NULL,
+    weight float8 NOT NULL,
+    height float8,
+    bmi float8,
+    fat_rate float8,
+    body_water_rate float8,
+    bone_mass float8,
+    metabolism float8,
+    muscle_rate float8,
+    visceral_fat float8
+);
@@ -0,0 +1,3 @@
+-- the algorithm sets which computed the values
+ALTER TABLE mifit
+    ADD COLUMN IF NOT EXISTS algorithm text;
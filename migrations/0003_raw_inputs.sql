@@ -0,0 +1,8 @@
+-- raw scale inputs and the profile used, for recomputing
+ALTER TABLE mifit
+    ADD COLUMN IF NOT EXISTS impedance float8,
+    ADD COLUMN IF NOT EXISTS muscle_kg float8,
+    ADD COLUMN IF NOT EXISTS sex smallint,
+    ADD COLUMN IF NOT EXISTS age float8,
+    ADD COLUMN IF NOT EXISTS profile text NOT NULL DEFAULT 'default',
+    ADD COLUMN IF NOT EXISTS flags int;
@@ -0,0 +1,8 @@
+-- trend weight, moving averages and weekly rates of change
+ALTER TABLE mifit
+    ADD COLUMN IF NOT EXISTS weight_trend float8,
+    ADD COLUMN IF NOT EXISTS weight_avg7 float8,
+    ADD COLUMN IF NOT EXISTS weight_avg30 float8,
+    ADD COLUMN IF NOT EXISTS weight_week_change float8,
+    ADD COLUMN IF NOT EXISTS fat_week_change float8,
+    ADD COLUMN IF NOT EXISTS muscle_week_change float8;
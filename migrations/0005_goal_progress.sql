@@ -0,0 +1,12 @@
+-- progress towards the configured goals
+CREATE TABLE IF NOT EXISTS goal_progress (
+    time timestamptz NOT NULL,
+    profile text NOT NULL,
+    metric text NOT NULL,
+    target float8 NOT NULL,
+    current float8 NOT NULL,
+    remaining float8 NOT NULL,
+    weekly_rate float8,
+    projected date
+);
+CREATE INDEX IF NOT EXISTS goal_progress_profile_time ON goal_progress (profile, time);
@@ -0,0 +1,29 @@
+-- measurements rejected as outliers
+CREATE TABLE IF NOT EXISTS mifit_quarantine (
+    id serial PRIMARY KEY,
+    time timestamptz NOT NULL,
+    weight float8 NOT NULL,
+    height float8,
+    bmi float8,
+    fat_rate float8,
+    body_water_rate float8,
+    bone_mass float8,
+    metabolism float8,
+    muscle_rate float8,
+    visceral_fat float8,
+    algorithm text,
+    impedance float8,
+    muscle_kg float8,
+    sex smallint,
+    age float8,
+    profile text NOT NULL DEFAULT 'default',
+    flags int,
+    weight_trend float8,
+    weight_avg7 float8,
+    weight_avg30 float8,
+    weight_week_change float8,
+    fat_week_change float8,
+    muscle_week_change float8,
+    reason text NOT NULL
+);
+CREATE INDEX IF NOT EXISTS mifit_quarantine_profile_time ON mifit_quarantine (profile, time);
@@ -0,0 +1,12 @@
+-- weekly energy balance figures
+CREATE TABLE IF NOT EXISTS energy_weekly (
+    profile text NOT NULL,
+    week date NOT NULL,
+    bmr float8,
+    tdee float8,
+    fat_mass_change float8,
+    lean_mass_change float8,
+    balance float8,
+    intake float8,
+    PRIMARY KEY (profile, week)
+);
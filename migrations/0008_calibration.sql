@@ -0,0 +1,23 @@
+-- reference measurements and the calibrated values
+CREATE TABLE IF NOT EXISTS reference_measurement (
+    id serial PRIMARY KEY,
+    profile text NOT NULL,
+    date date NOT NULL,
+    metric text NOT NULL,
+    value float8 NOT NULL,
+    source text NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reference_measurement_profile ON reference_measurement (profile, date);
+
+ALTER TABLE mifit
+    ADD COLUMN IF NOT EXISTS fat_rate_calibrated float8,
+    ADD COLUMN IF NOT EXISTS muscle_kg_calibrated float8,
+    ADD COLUMN IF NOT EXISTS body_water_rate_calibrated float8,
+    ADD COLUMN IF NOT EXISTS bone_mass_calibrated float8,
+    ADD COLUMN IF NOT EXISTS visceral_fat_calibrated float8;
+ALTER TABLE mifit_quarantine
+    ADD COLUMN IF NOT EXISTS fat_rate_calibrated float8,
+    ADD COLUMN IF NOT EXISTS muscle_kg_calibrated float8,
+    ADD COLUMN IF NOT EXISTS body_water_rate_calibrated float8,
+    ADD COLUMN IF NOT EXISTS bone_mass_calibrated float8,
+    ADD COLUMN IF NOT EXISTS visceral_fat_calibrated float8;
@@ -0,0 +1,12 @@
+-- one measurement per time and profile, also the index for the per-profile queries;
+-- the duplicates of a legacy table are moved to `mifit_duplicates` first,
+-- the row stored first stays in `mifit`
+CREATE TABLE IF NOT EXISTS mifit_duplicates (LIKE mifit);
+WITH moved AS (
+    DELETE FROM mifit a
+        USING mifit b
+        WHERE a.profile = b.profile AND a.time = b.time AND a.ctid > b.ctid
+        RETURNING a.*
+)
+INSERT INTO mifit_duplicates SELECT * FROM moved;
+CREATE UNIQUE INDEX IF NOT EXISTS mifit_profile_time ON mifit (profile, time);
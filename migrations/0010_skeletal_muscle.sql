@@ -0,0 +1,7 @@
+-- skeletal muscle mass, apart from the lean mass in muscle_kg
+ALTER TABLE mifit
+    ADD COLUMN IF NOT EXISTS skeletal_muscle_kg float8;
+ALTER TABLE mifit_quarantine
+    ADD COLUMN IF NOT EXISTS skeletal_muscle_kg float8;
+ALTER TABLE mifit_duplicates
+    ADD COLUMN IF NOT EXISTS skeletal_muscle_kg float8;
@@ -0,0 +1,8 @@
+-- one progress row per time, profile and metric, also the index for the per-profile queries;
+-- the rows are derived from the measurements, so of the duplicates stored by replaying
+-- the spool only one is kept
+DELETE FROM goal_progress a
+    USING goal_progress b
+    WHERE a.profile = b.profile AND a.time = b.time AND a.metric = b.metric AND a.ctid > b.ctid;
+DROP INDEX IF EXISTS goal_progress_profile_time;
+CREATE UNIQUE INDEX IF NOT EXISTS goal_progress_profile_time_metric ON goal_progress (profile, time, metric);
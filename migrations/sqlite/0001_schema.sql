@@ -0,0 +1,104 @@
+-- measurements, the same columns as in PostgreSQL (time in UTC)
+CREATE TABLE mifit (
+    time text NOT NULL,
+    weight real NOT NULL,
+    height real,
+    bmi real,
+    fat_rate real,
+    body_water_rate real,
+    bone_mass real,
+    metabolism real,
+    muscle_rate real,
+    visceral_fat real,
+    algorithm text,
+    impedance real,
+    muscle_kg real,
+    sex integer,
+    age real,
+    profile text NOT NULL DEFAULT 'default',
+    flags integer,
+    weight_trend real,
+    weight_avg7 real,
+    weight_avg30 real,
+    weight_week_change real,
+    fat_week_change real,
+    muscle_week_change real,
+    fat_rate_calibrated real,
+    muscle_kg_calibrated real,
+    body_water_rate_calibrated real,
+    bone_mass_calibrated real,
+    visceral_fat_calibrated real
+);
+CREATE UNIQUE INDEX mifit_profile_time ON mifit (profile, time);
+
+-- measurements rejected as outliers
+CREATE TABLE mifit_quarantine (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    time text NOT NULL,
+    weight real NOT NULL,
+    height real,
+    bmi real,
+    fat_rate real,
+    body_water_rate real,
+    bone_mass real,
+    metabolism real,
+    muscle_rate real,
+    visceral_fat real,
+    algorithm text,
+    impedance real,
+    muscle_kg real,
+    sex integer,
+    age real,
+    profile text NOT NULL DEFAULT 'default',
+    flags integer,
+    weight_trend real,
+    weight_avg7 real,
+    weight_avg30 real,
+    weight_week_change real,
+    fat_week_change real,
+    muscle_week_change real,
+    fat_rate_calibrated real,
+    muscle_kg_calibrated real,
+    body_water_rate_calibrated real,
+    bone_mass_calibrated real,
+    visceral_fat_calibrated real,
+    reason text NOT NULL
+);
+CREATE INDEX mifit_quarantine_profile_time ON mifit_quarantine (profile, time);
+
+-- progress towards the configured goals
+CREATE TABLE goal_progress (
+    time text NOT NULL,
+    profile text NOT NULL,
+    metric text NOT NULL,
+    target real NOT NULL,
+    current real NOT NULL,
+    remaining real NOT NULL,
+    weekly_rate real,
+    projected text
+);
+CREATE INDEX goal_progress_profile_time ON goal_progress (profile, time);
+
+-- weekly energy balance figures
+CREATE TABLE energy_weekly (
+    profile text NOT NULL,
+    week text NOT NULL,
+    bmr real,
+    tdee real,
+    fat_mass_change real,
+    lean_mass_change real,
+    balance real,
+    intake real,
+    PRIMARY KEY (profile, week)
+);
+
+-- reference measurements for the calibration
+CREATE TABLE reference_measurement (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    profile text NOT NULL,
+    date text NOT NULL,
+    metric text NOT NULL,
+    value real NOT NULL,
+    source text NOT NULL
+);
+CREATE INDEX reference_measurement_profile ON reference_measurement (profile, date);
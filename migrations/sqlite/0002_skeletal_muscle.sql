@@ -0,0 +1,3 @@
+-- skeletal muscle mass, apart from the lean mass in muscle_kg
+ALTER TABLE mifit ADD COLUMN skeletal_muscle_kg real;
+ALTER TABLE mifit_quarantine ADD COLUMN skeletal_muscle_kg real;
@@ -0,0 +1,7 @@
+-- one progress row per time, profile and metric, also the index for the per-profile queries;
+-- the rows are derived from the measurements, so of the duplicates stored by replaying
+-- the spool only one is kept
+DELETE FROM goal_progress
+    WHERE rowid NOT IN (SELECT min(rowid) FROM goal_progress GROUP BY profile, time, metric);
+DROP INDEX goal_progress_profile_time;
+CREATE UNIQUE INDEX goal_progress_profile_time_metric ON goal_progress (profile, time, metric);
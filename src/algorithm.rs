@@ -0,0 +1,275 @@
+use crate::body::Inputs;
+
+/// A set of body composition formulas.
+///
+/// Every metric is optional: a set which doesn't provide a formula for some
+/// metric returns `None` and the next configured set (finally `mifit`) is used.
+pub trait Algorithm: Sync {
+    /// Name used in the config file and recorded with the measurement
+    fn name(&self) -> &'static str;
+
+    /// Body fat (%)
+    fn body_fat(&self, _i: &Inputs) -> Option<f32> {
+        None
+    }
+
+    /// Body water (%) with the body fat `bf` (%) of the selected set
+    fn water(&self, _i: &Inputs, _bf: f32) -> Option<f32> {
+        None
+    }
+
+    /// Muscle (lean) mass (kg) with the body fat `bf` (%) of the selected set
+    fn muscle(&self, _i: &Inputs, _bf: f32) -> Option<f32> {
+        None
+    }
+
+    /// Skeletal muscle mass (kg), a part of the muscle mass
+    fn skeletal_muscle(&self, _i: &Inputs) -> Option<f32> {
+        None
+    }
+
+    /// Bone mass (kg)
+    fn bone_mass(&self, _i: &Inputs) -> Option<f32> {
+        None
+    }
+
+    /// Visceral fat (rating)
+    fn visceral_fat(&self, _i: &Inputs) -> Option<f32> {
+        None
+    }
+
+    /// Basal metabolism (kcal)
+    fn bmr(&self, _i: &Inputs) -> Option<f32> {
+        None
+    }
+}
+
+/// All known algorithm sets
+pub static ALGORITHMS: &[&dyn Algorithm] = &[&MiFit, &Deurenberg, &Kyle, &Janssen, &Sun];
+
+/// Find the algorithm set by its config name
+pub fn find(name: &str) -> Option<&'static dyn Algorithm> {
+    ALGORITHMS
+        .iter()
+        .find(|a| a.name().eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Fat-free mass to body fat percentage
+fn ffm_to_body_fat(ffm: f32, weight: f32) -> f32 {
+    (weight - ffm) / weight * 100.0
+}
+
+/// Formulas used in the former `MiFit` and current `Zepp Life` android app
+/// (ported from openScale), with the basal metabolism taken from ha-miscale2.
+pub struct MiFit;
+
+impl MiFit {
+    pub fn get_lbm_coefficient(&self, i: &Inputs) -> f32 {
+        let mut lbm: f32 = (i.height * 9.058 / 100.0) * (i.height / 100.0);
+        lbm += i.weight * 0.32 + 12.226;
+        lbm -= i.impedance * 0.0068;
+        lbm -= i.age * 0.0542;
+
+        lbm
+    }
+
+    pub fn get_muscle(&self, i: &Inputs, bf: f32) -> f32 {
+        let mut muscle_mass: f32 = i.weight - ((bf * 0.01) * i.weight) - self.get_bone_mass(i);
+
+        if (i.sex == 0 && muscle_mass >= 84.0) || (i.sex == 1 && muscle_mass >= 93.5) {
+            muscle_mass = 120.0;
+        }
+
+        muscle_mass
+    }
+
+    pub fn get_water(&self, bf: f32) -> f32 {
+        let water: f32 = (100.0 - bf) * 0.7;
+        let coeff: f32 = if water < 50.0 { 1.02 } else { 0.98 };
+
+        coeff * water
+    }
+
+    #[allow(clippy::excessive_precision)]
+    pub fn get_bone_mass(&self, i: &Inputs) -> f32 {
+        let base: f32 = if i.sex == 0 { 0.245691014 } else { 0.18016894 };
+
+        let mut bone_mass: f32 = -(base - (self.get_lbm_coefficient(i) * 0.05158));
+
+        if bone_mass > 2.2 {
+            bone_mass += 0.1;
+        } else {
+            bone_mass -= 0.1;
+        }
+
+        if (i.sex == 0 && bone_mass > 5.1) || (i.sex == 1 && bone_mass > 5.2) {
+            bone_mass = 8.0;
+        }
+
+        bone_mass
+    }
+
+    pub fn get_visceral_fat(&self, i: &Inputs) -> f32 {
+        if i.sex == 0 {
+            if i.weight > -(13.0 - (i.height * 0.5)) {
+                let subsubcalc: f32 = ((i.height * 1.45) + (i.height * 0.1158) * i.height) - 120.0;
+                let subcalc: f32 = i.weight * 500.0 / subsubcalc;
+                (subcalc - 6.0) + (i.age * 0.07)
+            } else {
+                let subcalc: f32 = 0.691 + (i.height * -0.0024) + (i.height * -0.0024);
+                -((i.height * 0.027) - (subcalc * i.weight)) + (i.age * 0.07) - i.age
+            }
+        } else if i.height < i.weight * 1.6 {
+            let subcalc: f32 = -((i.height * 0.4) - (i.height * (i.height * 0.0826)));
+            ((i.weight * 305.0) / (subcalc + 48.0)) - 2.9 + (i.age * 0.15)
+        } else {
+            let subcalc: f32 = 0.765 + i.height * -0.0015;
+            -((i.height * 0.143) - (i.weight * subcalc)) + (i.age * 0.15) - 5.0
+        }
+    }
+
+    pub fn get_body_fat(&self, i: &Inputs) -> f32 {
+        let mut lbm_sub: f32 = 0.8;
+
+        if i.sex == 0 && i.age <= 49.0 {
+            lbm_sub = 9.25;
+        } else if i.sex == 0 && i.age > 49.0 {
+            lbm_sub = 7.25;
+        }
+
+        let lbm_coeff: f32 = self.get_lbm_coefficient(i);
+        let mut coeff: f32 = 1.0;
+
+        if i.sex == 1 && i.weight < 61.0 {
+            coeff = 0.98;
+        } else if i.sex == 0 && i.weight > 60.0 {
+            coeff = 0.96;
+
+            if i.height > 160.0 {
+                coeff *= 1.03;
+            }
+        } else if i.sex == 0 && i.weight < 50.0 {
+            coeff = 1.02;
+
+            if i.height > 160.0 {
+                coeff *= 1.03;
+            }
+        }
+
+        let mut body_fat: f32 = (1.0 - (((lbm_coeff - lbm_sub) * coeff) / i.weight)) * 100.0;
+
+        if body_fat > 63.0 {
+            body_fat = 75.0;
+        }
+
+        body_fat
+    }
+
+    pub fn get_bmr(&self, i: &Inputs) -> f32 {
+        let mut bmr: f32;
+
+        if i.sex == 0 {
+            //female
+            bmr = 864.6 + i.weight * 10.2036;
+            bmr -= i.height * 0.39336;
+            bmr -= i.age * 6.204;
+        } else {
+            bmr = 877.8 + i.weight * 14.916;
+            bmr -= i.height * 0.726;
+            bmr -= i.age * 8.976;
+        }
+
+        bmr
+    }
+}
+
+impl Algorithm for MiFit {
+    fn name(&self) -> &'static str {
+        "mifit"
+    }
+    fn body_fat(&self, i: &Inputs) -> Option<f32> {
+        Some(self.get_body_fat(i))
+    }
+    fn water(&self, _i: &Inputs, bf: f32) -> Option<f32> {
+        Some(self.get_water(bf))
+    }
+    fn muscle(&self, i: &Inputs, bf: f32) -> Option<f32> {
+        Some(self.get_muscle(i, bf))
+    }
+    fn bone_mass(&self, i: &Inputs) -> Option<f32> {
+        Some(self.get_bone_mass(i))
+    }
+    fn visceral_fat(&self, i: &Inputs) -> Option<f32> {
+        Some(self.get_visceral_fat(i))
+    }
+    fn bmr(&self, i: &Inputs) -> Option<f32> {
+        Some(self.get_bmr(i))
+    }
+}
+
+/// Deurenberg et al. (1991), body fat from BMI, age and sex (no impedance)
+pub struct Deurenberg;
+
+impl Algorithm for Deurenberg {
+    fn name(&self) -> &'static str {
+        "deurenberg"
+    }
+    fn body_fat(&self, i: &Inputs) -> Option<f32> {
+        Some(1.2 * i.bmi() + 0.23 * i.age - 10.8 * i.sex as f32 - 5.4)
+    }
+}
+
+/// Kyle et al. (2001), fat-free mass from BIA at 50 kHz.
+///
+/// The scale doesn't report the reactance, so it is estimated from the
+/// impedance assuming a typical phase angle of 6.5°.
+pub struct Kyle;
+
+impl Algorithm for Kyle {
+    fn name(&self) -> &'static str {
+        "kyle"
+    }
+    fn body_fat(&self, i: &Inputs) -> Option<f32> {
+        let reactance = i.impedance * 6.5_f32.to_radians().tan();
+        let ffm = -4.104
+            + 0.518 * i.height * i.height / i.impedance
+            + 0.231 * i.weight
+            + 0.130 * reactance
+            + 4.229 * i.sex as f32;
+        Some(ffm_to_body_fat(ffm, i.weight))
+    }
+}
+
+/// Janssen et al. (2000), skeletal muscle mass from BIA
+pub struct Janssen;
+
+impl Algorithm for Janssen {
+    fn name(&self) -> &'static str {
+        "janssen"
+    }
+    fn skeletal_muscle(&self, i: &Inputs) -> Option<f32> {
+        Some(
+            i.height * i.height / i.impedance * 0.401 + i.sex as f32 * 3.825 - i.age * 0.071
+                + 5.102,
+        )
+    }
+}
+
+/// Sun et al. (2003), fat-free mass from BIA (NHANES III)
+pub struct Sun;
+
+impl Algorithm for Sun {
+    fn name(&self) -> &'static str {
+        "sun"
+    }
+    fn body_fat(&self, i: &Inputs) -> Option<f32> {
+        let ht2_r = i.height * i.height / i.impedance;
+        let ffm = if i.sex == 0 {
+            -9.53 + 0.69 * ht2_r + 0.17 * i.weight + 0.02 * i.impedance
+        } else {
+            -10.68 + 0.65 * ht2_r + 0.26 * i.weight + 0.02 * i.impedance
+        };
+        Some(ffm_to_body_fat(ffm, i.weight))
+    }
+}
@@ -1,16 +1,21 @@
 use crate::body::*;
-use crate::Database;
-use bitops::BitOps;
+use crate::frame::Frame;
+use crate::sink::{Sink, SinkSpool};
+use crate::storage::{Storage, StoredReading};
+use crate::trend::{Trend, RATE_WINDOW_DAYS};
 use bluer::{Adapter, Address};
-use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use simplelog::*;
 
 const SERVICE_UUID: uuid::Uuid = uuid::Uuid::from_u128(0x0000181b00001000800000805f9b34fb);
 
+/// Read the last measurement of the scale and deliver it to the sinks
+/// (or keep it in the spool of the sink until it is reachable)
 pub async fn query_device(
     adapter: &Adapter,
     addr: Address,
-    db: &mut Database,
+    db: &dyn Storage,
+    sinks: &[SinkSpool],
     p: Person,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let device = adapter.device(addr)?;
@@ -34,103 +39,321 @@ pub async fn query_device(
     );
     let x = device.service_data().await?;
     debug!("    Service data:       {:?}", x);
-    //todo: check if vec size=13
-    match x {
-        Some(x) => parse(x.get(&SERVICE_UUID).unwrap().to_vec(), db, p).await,
+    match x.as_ref().and_then(|x| x.get(&SERVICE_UUID)) {
+        Some(data) => parse(data.to_vec(), db, sinks, p).await,
         None => Err("No service data".into()),
     }
 }
 
 /// Check if a input `DateTime` occurs in range of the specified duration from now.
+#[allow(clippy::let_and_return)]
 pub fn in_range(input_dt: DateTime<Utc>, range_dur: Duration) -> bool {
     let utc_now_dt = Utc::now();
     let within_range = input_dt >= utc_now_dt - range_dur && input_dt <= utc_now_dt + range_dur;
     within_range
 }
 
+/// Calibrate the measurement and compute its trend from the stored history,
+/// returns the reason when the measurement is an outlier
+async fn prepare(db: &dyn Storage, m: &mut Measurement, p: &Person) -> Option<String> {
+    match db.fetch_calibration(&p.name).await {
+        Ok(calibration) => m.calibrated = calibration.apply(m),
+        Err(e) => {
+            // the database is likely unreachable, don't wait for it again
+            warn!("Cannot fetch the calibration: {}", e);
+            return None;
+        }
+    }
+
+    let since = m.date_time - Duration::days(RATE_WINDOW_DAYS);
+    match db.fetch_samples(&p.name, Some(since)).await {
+        Ok(mut history) => {
+            // a replayed measurement may be stored already (by another sink)
+            history.retain(|s| s.date_time < m.date_time);
+            if let Some(reason) = p.outlier.check(&history, m) {
+                warn!("🚷 Measurement rejected as an outlier: {}", reason);
+                return Some(reason);
+            }
+            let trend = Trend::compute(&history, &m.sample());
+            info!("📈 {}", trend);
+            m.trend = Some(trend);
+        }
+        Err(e) => warn!("Cannot compute the trend: {}", e),
+    }
+
+    None
+}
+
+/// Deliver the measurement to the sink (outliers to its quarantine)
+async fn deliver(
+    sink: &dyn Sink,
+    m: &Measurement,
+    p: &Person,
+    outlier: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match outlier {
+        Some(reason) => sink.quarantine(m, p, reason).await,
+        None => sink.send(m, p).await,
+    }
+}
+
 async fn parse(
     data: Vec<u8>,
-    db: &mut Database,
+    db: &dyn Storage,
+    sinks: &[SinkSpool],
     p: Person,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let ctrl_byte0: u8 = data[0];
-    let ctrl_byte1: u8 = data[1];
-
-    let _is_weight_removed: bool = ctrl_byte1.is_bit_set(7);
-    let is_date_invalid: bool = ctrl_byte1.is_bit_set(6);
-    let is_stabilized: bool = ctrl_byte1.is_bit_set(5);
-    let is_lbs_unit: bool = ctrl_byte0.is_bit_set(0);
-    let is_catty_unit: bool = ctrl_byte1.is_bit_set(6);
-    let is_impedance: bool = ctrl_byte1.is_bit_set(1);
-
-    if is_stabilized && /* is_weight_removed &&*/ !is_date_invalid {
-        let year: u16 = ((data[3] as u16) << 8) + data[2] as u16;
-        let month: u8 = data[4];
-        let day: u8 = data[5];
-        let hours: u8 = data[6];
-        let min: u8 = data[7];
-        let sec: u8 = data[8];
-
-        let weight: f32;
-        let mut impedance: f32 = 0.0;
-
-        if is_lbs_unit || is_catty_unit {
-            weight = (((data[12] as u16) << 8) + data[11] as u16) as f32 / 100.0;
-        } else {
-            weight = (((data[12] as u16) << 8) + data[11] as u16) as f32 / 200.0;
-        }
+    let frame = Frame::decode(&data)?;
+    if frame.impedance != 0.0 {
+        debug!("Impedance value: {}", frame.impedance);
+    }
 
-        if is_impedance {
-            impedance = (((data[10] as u16) << 8) + data[9] as u16) as f32;
-            debug!("Impedance value: {}", impedance);
-        }
+    // is the timestamp plausible? check if it is in the range of 10 minutes...
+    if in_range(
+        Utc.from_utc_datetime(&frame.date_time),
+        Duration::minutes(10),
+    ) {
+        if frame.impedance != 0.0 {
+            let mut m = p.measure(frame.date_time, frame.weight, frame.impedance, frame.flags);
+            debug!("Computed measurement:\n{}", m);
 
-        let date_time: NaiveDateTime = NaiveDate::from_ymd(year as i32, month as u32, day as u32)
-            .and_hms(hours as u32, min as u32, sec as u32);
-
-        // is the timestamp plausible? check if it is in the range of 10 minutes...
-        if in_range(Utc.from_utc_datetime(&date_time), Duration::minutes(10)) {
-            if impedance != 0.0 {
-                let muscle_kg = p.get_muscle(weight, impedance);
-                let m = Measurement {
-                    date_time,
-                    weight,
-                    bmi: p.get_bmi(weight),
-                    water_rate: p.get_water(weight, impedance),
-                    bmr: p.get_bmr(weight),
-                    visceral_fat: p.get_visceral_fat(weight),
-                    bf: p.get_body_fat(weight, impedance),
-                    muscle_kg,
-                    muscle_rate: (100.0 / weight) * muscle_kg, // convert muscle in kg to percent
-                    bone_mass: p.get_bone_mass(weight, impedance),
-                };
-                debug!("Computed measurement:\n{}", m);
-
-                let mut db_cloned = db.clone();
-                match tokio::task::spawn_blocking(move || {
-                    info!("🛢️  Storing measurement in the database");
-                    if db_cloned.insert_data(m, &p) {
-                        Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
-                    } else {
-                        Err("Database insert has failed".into())
+            // each sink keeps the measurement on disk until it is delivered
+            let spooled: Vec<_> = sinks
+                .iter()
+                .map(|s| {
+                    let reading = StoredReading {
+                        date_time: frame.date_time,
+                        weight: frame.weight,
+                        impedance: frame.impedance,
+                        flags: frame.flags,
+                    };
+                    let spooled = s.spool.push(&p.name, reading);
+                    if let Err(e) = &spooled {
+                        error!(
+                            "{}: cannot write the measurement to the spool: {}",
+                            s.sink.name(),
+                            e
+                        );
                     }
+                    spooled
                 })
-                .await
-                {
-                    Ok(_) => {
-                        return Ok(());
-                    }
-                    Err(_) => {
-                        return Err("Error spawning DB thread".into());
+                .collect();
+
+            let outlier = prepare(db, &mut m, &p).await;
+
+            let mut lost = vec![];
+            for (s, spooled) in sinks.iter().zip(spooled) {
+                let delivered = deliver(s.sink.as_ref(), &m, &p, outlier.as_deref()).await;
+                if let Err(e) = &delivered {
+                    error!("{}: cannot deliver the measurement: {}", s.sink.name(), e);
+                }
+                match (delivered, spooled) {
+                    (Ok(()), Ok(entry)) => {
+                        if let Err(e) = s.spool.remove(&entry) {
+                            warn!(
+                                "Cannot remove {} from the spool: {}",
+                                entry.path.display(),
+                                e
+                            );
+                        }
                     }
+                    (Ok(()), Err(_)) => (),
+                    (Err(_), Ok(_)) => warn!(
+                        "📥 {}: measurement kept in the spool, it will be delivered when the sink is reachable",
+                        s.sink.name()
+                    ),
+                    (Err(_), Err(_)) => lost.push(s.sink.name()),
                 }
+            }
+
+            if lost.is_empty() {
+                Ok(())
             } else {
-                return Err("Impedance value is zero".into());
+                Err(format!("Delivery has failed: {}", lost.join(", ")).into())
             }
         } else {
-            return Err("Error: invalid datetime for mi scale data".into());
+            // the weight is stabilised before the impedance is measured
+            for s in sinks {
+                if let Err(e) = s.sink.live_weight(frame.weight, &p).await {
+                    warn!("{}: cannot send the live weight: {}", s.sink.name(), e);
+                }
+            }
+            Err("Impedance value is zero".into())
         }
     } else {
-        return Err("Invalid scale data (eg. not stabilised)".into());
+        Err("Error: invalid datetime for mi scale data".into())
+    }
+}
+
+/// Deliver the spooled measurements of the profile to each sink in order,
+/// stopping at the first failure of the sink (the other sinks are not affected).
+///
+/// Returns the number of the measurements delivered.
+pub async fn flush_spool(
+    sinks: &[SinkSpool],
+    db: &dyn Storage,
+    p: &Person,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut delivered = 0;
+    let mut failed = vec![];
+    for s in sinks {
+        let mut count = 0;
+        for entry in s.spool.entries()? {
+            if entry.profile != p.name {
+                warn!(
+                    "Skipping {}: no profile configured for user `{}`",
+                    entry.path.display(),
+                    entry.profile
+                );
+                continue;
+            }
+            let r = &entry.reading;
+            info!(
+                "📤 {}: delivering the spooled measurement from {}",
+                s.sink.name(),
+                r.date_time
+            );
+            let mut m = p.measure(r.date_time, r.weight, r.impedance, r.flags);
+            let outlier = prepare(db, &mut m, p).await;
+            if let Err(e) = deliver(s.sink.as_ref(), &m, p, outlier.as_deref()).await {
+                error!("{}: cannot deliver the measurement: {}", s.sink.name(), e);
+                failed.push(format!(
+                    "{}: failed after {} measurements",
+                    s.sink.name(),
+                    count
+                ));
+                break;
+            }
+            s.spool.remove(&entry)?;
+            count += 1;
+        }
+        delivered += count;
+    }
+
+    if failed.is_empty() {
+        Ok(delivered)
+    } else {
+        Err(failed.join(", ").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::tests::FakeSink;
+    use crate::spool::Spool;
+    use crate::sqlite::Sqlite;
+    use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+    use futures::executor::block_on;
+    use std::path::Path;
+
+    fn person() -> Person {
+        crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap())
+    }
+
+    fn database() -> Sqlite {
+        let db = Sqlite::open("test", Path::new(":memory:")).unwrap();
+        block_on(db.migrate()).unwrap();
+        db
+    }
+
+    /// Fake sink with its own empty spool
+    fn sink(name: &str) -> (FakeSink, SinkSpool) {
+        let dir =
+            std::env::temp_dir().join(format!("bluescale-sink-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let spool = Spool::new(&dir);
+        spool.open().unwrap();
+        let fake = FakeSink::default();
+        let sink = SinkSpool {
+            sink: Box::new(fake.clone()),
+            spool,
+        };
+        (fake, sink)
+    }
+
+    /// Complete frame (80 kg, 500 Ω) of a measurement taken now
+    fn frame(now: NaiveDateTime) -> Vec<u8> {
+        let year = (now.year() as u16).to_le_bytes();
+        let impedance = 500u16.to_le_bytes();
+        let weight = (80 * 200u16).to_le_bytes();
+        vec![
+            0x02,
+            0xa6,
+            year[0],
+            year[1],
+            now.month() as u8,
+            now.day() as u8,
+            now.hour() as u8,
+            now.minute() as u8,
+            now.second() as u8,
+            impedance[0],
+            impedance[1],
+            weight[0],
+            weight[1],
+        ]
+    }
+
+    fn reading(hour: u32) -> StoredReading {
+        StoredReading {
+            date_time: NaiveDate::from_ymd_opt(2026, 10, 18)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap(),
+            weight: 80.0,
+            impedance: 500.0,
+            flags: 0x02,
+        }
+    }
+
+    #[test]
+    fn failed_delivery_kept_in_spool() {
+        let db = database();
+        let (failing, failing_sink) = sink("parse-failing");
+        let (working, working_sink) = sink("parse-working");
+        failing.failing.set(true);
+        let sinks = [failing_sink, working_sink];
+        let now = Utc::now().naive_utc().with_nanosecond(0).unwrap();
+
+        block_on(parse(frame(now), &db, &sinks, person())).unwrap();
+        assert_eq!(working.sent.borrow().as_slice(), [now]);
+        assert!(sinks[1].spool.entries().unwrap().is_empty());
+        assert!(failing.sent.borrow().is_empty());
+        let entries = sinks[0].spool.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reading.date_time, now);
+
+        // delivered on the next flush once the sink is reachable
+        failing.failing.set(false);
+        assert_eq!(block_on(flush_spool(&sinks, &db, &person())).unwrap(), 1);
+        assert_eq!(failing.sent.borrow().as_slice(), [now]);
+        assert_eq!(working.sent.borrow().len(), 1);
+        assert!(sinks[0].spool.entries().unwrap().is_empty());
+
+        for s in &sinks {
+            std::fs::remove_dir_all(&s.spool.dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn flush_stops_at_first_failure() {
+        let db = database();
+        let (fake, sink) = sink("flush");
+        sink.spool.push("test", reading(9)).unwrap();
+        sink.spool.push("test", reading(8)).unwrap();
+        let sinks = [sink];
+
+        fake.failing.set(true);
+        assert!(block_on(flush_spool(&sinks, &db, &person())).is_err());
+        assert_eq!(sinks[0].spool.entries().unwrap().len(), 2);
+
+        fake.failing.set(false);
+        assert_eq!(block_on(flush_spool(&sinks, &db, &person())).unwrap(), 2);
+        assert_eq!(
+            fake.sent.borrow().as_slice(),
+            [reading(8).date_time, reading(9).date_time]
+        );
+        assert!(sinks[0].spool.entries().unwrap().is_empty());
+        std::fs::remove_dir_all(&sinks[0].spool.dir).unwrap();
     }
 }
@@ -1,18 +1,77 @@
-use chrono::NaiveDateTime;
+use crate::algorithm::{Algorithm, MiFit, ALGORITHMS};
+use crate::calibration::Calibrated;
+use crate::goal::Goal;
+use crate::outlier::OutlierFilter;
+use crate::rating::{
+    bmi_for_age, rate_bmi, rate_body_fat, schofield_bmr, BmiForAge, Rating, ADULT_AGE,
+    VALIDATED_MAX_AGE,
+};
+use crate::trend::{Sample, Trend};
+use crate::uncertainty::{InputUncertainty, Uncertainty};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{Serialize, Serializer};
 use std::fmt;
 
-#[derive(Clone)]
-pub struct Person {
+/// Inputs of the body composition formulas
+#[derive(Clone, Copy)]
+pub struct Inputs {
     pub sex: u8, // male = 1; female = 0
     pub age: f32,
     pub height: f32,
+    pub weight: f32,
+    pub impedance: f32,
+}
+
+impl Inputs {
+    pub fn bmi(&self) -> f32 {
+        self.weight / (((self.height * self.height) / 100.0) / 100.0)
+    }
+}
+
+/// Profile of the person using the scale
+#[derive(Clone, Serialize)]
+pub struct Person {
+    /// profile name stored with each measurement
+    pub name: String,
+    pub sex: u8, // male = 1; female = 0
+    pub birthday: NaiveDate,
+    pub height: f32,
+    /// algorithm sets in order of preference, `mifit` is always the last resort
+    #[serde(serialize_with = "serialize_algorithms")]
+    pub algorithms: Vec<&'static dyn Algorithm>,
+    pub goals: Vec<Goal>,
+    pub outlier: OutlierFilter,
+    /// physical activity factor multiplying the basal metabolism
+    pub activity: f32,
+    /// uncertainty of the scale inputs
+    pub uncertainty: InputUncertainty,
+}
+
+/// Profile name usable in file names and MQTT topics: the path and topic
+/// separators, the MQTT wildcards and anything else unusual are replaced
+pub fn safe_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// The algorithm sets are serialized by their names
+fn serialize_algorithms<S: Serializer>(
+    algorithms: &[&'static dyn Algorithm],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(algorithms.iter().map(|a| a.name()))
 }
 
 impl fmt::Display for Person {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "sex: {}, age: {}, height: {} cm",
+            "{}, sex: {}, birthday: {}, height: {} cm, algorithm: {}",
+            self.name,
             {
                 if self.sex == 1 {
                     "Male ♂️ "
@@ -20,191 +79,542 @@ impl fmt::Display for Person {
                     "Female ♀️ "
                 }
             },
-            self.age,
-            self.height
+            self.birthday,
+            self.height,
+            self.algorithm_names()
         )?;
         Ok(())
     }
 }
 
+/// Names of the algorithm sets which produced each value of the `Measurement`
+#[derive(Clone, Copy, Serialize)]
+pub struct Sources {
+    pub water_rate: &'static str,
+    pub bmr: &'static str,
+    pub visceral_fat: &'static str,
+    pub bf: &'static str,
+    pub muscle: &'static str,
+    pub skeletal_muscle: &'static str,
+    pub bone_mass: &'static str,
+}
+
+impl fmt::Display for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "water={},bmr={},visceral_fat={},fat={},muscle={},skeletal_muscle={},bone={}",
+            self.water_rate,
+            self.bmr,
+            self.visceral_fat,
+            self.bf,
+            self.muscle,
+            self.skeletal_muscle,
+            self.bone_mass
+        )
+    }
+}
+
+impl Sources {
+    /// Sources as stored in the `algorithm` column (the `Display` format), a single
+    /// name is the source of every value and unknown names are shown as `stored`
+    pub fn parse(s: &str) -> Sources {
+        let known = |name: &str| {
+            ALGORITHMS
+                .iter()
+                .map(|a| a.name())
+                .chain(["schofield", "imported", "csv", "openscale", "zepp", "-"])
+                .find(|known| *known == name)
+                .unwrap_or("stored")
+        };
+        let source = |key: &str| {
+            if !s.contains('=') {
+                return known(s);
+            }
+            s.split(',')
+                .find_map(|pair| pair.split_once('=').filter(|(k, _)| *k == key))
+                .map_or("-", |(_, name)| known(name))
+        };
+        Sources {
+            water_rate: source("water"),
+            bmr: source("bmr"),
+            visceral_fat: source("visceral_fat"),
+            bf: source("fat"),
+            muscle: source("muscle"),
+            skeletal_muscle: source("skeletal_muscle"),
+            bone_mass: source("bone"),
+        }
+    }
+}
+
+/// Body composition values known from elsewhere, eg. imported from another application
+#[derive(Clone, Copy, Default)]
+pub struct Composition {
+    pub water_rate: Option<f32>,
+    pub bmr: Option<f32>,
+    pub visceral_fat: Option<f32>,
+    pub bf: Option<f32>,
+    pub muscle_kg: Option<f32>,
+    pub bone_mass: Option<f32>,
+}
+
+/// Body composition computed from a single weigh-in
+#[derive(Serialize)]
 pub struct Measurement {
     pub date_time: NaiveDateTime,
     pub weight: f32,
+    /// raw scale inputs, the impedance is 0 when unknown (imported measurements)
+    pub impedance: f32,
+    pub flags: u16,
+    /// age of the person at `date_time`
+    pub age: f32,
     pub bmi: f32,
-    pub water_rate: f32,
+    /// the adult body composition values are not estimated under 18 years
+    pub water_rate: Option<f32>,
     pub bmr: f32,
-    pub visceral_fat: f32,
-    pub bf: f32,
-    pub muscle_kg: f32,
-    pub muscle_rate: f32,
-    pub bone_mass: f32,
+    pub visceral_fat: Option<f32>,
+    pub bf: Option<f32>,
+    pub muscle_kg: Option<f32>,
+    pub muscle_rate: Option<f32>,
+    /// skeletal muscle mass, only from the sets providing it (not `mifit`)
+    pub skeletal_muscle_kg: Option<f32>,
+    pub bone_mass: Option<f32>,
+    pub sources: Sources,
+    /// rating of the adult BMI
+    pub bmi_rating: Option<Rating>,
+    /// BMI-for-age of children and adolescents
+    pub bmi_for_age: Option<BmiForAge>,
+    /// rating of the adult body fat
+    pub fat_rating: Option<Rating>,
+    /// formulas used outside of their validated range etc.
+    pub warnings: Vec<String>,
+    /// trend at this measurement, computed from the stored history
+    pub trend: Option<Trend>,
+    /// values corrected by the per-profile calibration
+    pub calibrated: Calibrated,
+    /// 95% confidence intervals of the values
+    pub uncertainty: Option<Uncertainty>,
+}
+
+/// Value with the half-width of its confidence interval, if known
+fn with_uncertainty(value: f32, u: Option<f32>) -> String {
+    match u {
+        Some(u) => format!("{:.2} ± {:.2}", value, u),
+        None => value.to_string(),
+    }
 }
 
 impl fmt::Display for Measurement {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "    datetime: {}\n", self.date_time)?;
-        write!(f, "    weight: {} kg\n", self.weight)?;
-        write!(f, "    BMI: {}\n", self.bmi)?;
-        write!(f, "    water: {} %\n", self.water_rate)?;
-        write!(f, "    basal metabolism: {} kcal\n", self.bmr)?;
-        write!(f, "    visceral fat: {} %\n", self.visceral_fat)?;
-        write!(f, "    body fat: {} %\n", self.bf)?;
-        write!(f, "    lean body mass: {} %\n", self.muscle_rate)?;
-        write!(f, "    lean body mass: {} kg\n", self.muscle_kg)?;
-        write!(f, "    bone mass: {} kg\n", self.bone_mass)?;
+        let s = &self.sources;
+        let u = self.uncertainty;
+        let v = |value: f32, field: fn(&Uncertainty) -> f32| {
+            with_uncertainty(value, u.as_ref().map(field))
+        };
+        let opt = |value: Option<f32>, field: fn(&Uncertainty) -> f32| match value {
+            Some(value) => v(value, field),
+            None => "-".to_string(),
+        };
+        writeln!(f, "    datetime: {}", self.date_time)?;
+        writeln!(f, "    weight: {} kg", v(self.weight, |u| u.weight))?;
+        writeln!(f, "    impedance: {} Ω", self.impedance)?;
+        writeln!(f, "    age: {}", self.age)?;
+        write!(f, "    BMI: {}", v(self.bmi, |u| u.bmi))?;
+        if let Some(rating) = self.bmi_rating {
+            write!(f, " ({})", rating)?;
+        }
+        writeln!(f)?;
+        if let Some(bmi_for_age) = self.bmi_for_age {
+            writeln!(f, "    BMI-for-age: {}", bmi_for_age)?;
+        }
+        writeln!(
+            f,
+            "    water: {} % [{}]",
+            opt(self.water_rate, |u| u.water_rate),
+            s.water_rate
+        )?;
+        writeln!(
+            f,
+            "    basal metabolism: {} kcal [{}]",
+            v(self.bmr, |u| u.bmr),
+            s.bmr
+        )?;
+        writeln!(
+            f,
+            "    visceral fat: {} % [{}]",
+            opt(self.visceral_fat, |u| u.visceral_fat),
+            s.visceral_fat
+        )?;
+        write!(f, "    body fat: {} % [{}]", opt(self.bf, |u| u.bf), s.bf)?;
+        if let Some(rating) = self.fat_rating {
+            write!(f, " ({})", rating)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "    lean body mass: {} % [{}]",
+            opt(self.muscle_rate, |u| u.muscle_rate),
+            s.muscle
+        )?;
+        writeln!(
+            f,
+            "    lean body mass: {} kg [{}]",
+            opt(self.muscle_kg, |u| u.muscle_kg),
+            s.muscle
+        )?;
+        if let Some(skeletal_muscle_kg) = self.skeletal_muscle_kg {
+            writeln!(
+                f,
+                "    skeletal muscle mass: {} kg [{}]",
+                skeletal_muscle_kg, s.skeletal_muscle
+            )?;
+        }
+        writeln!(
+            f,
+            "    bone mass: {} kg [{}]",
+            opt(self.bone_mass, |u| u.bone_mass),
+            s.bone_mass
+        )?;
+        for warning in &self.warnings {
+            writeln!(f, "    ⚠️  {}", warning)?;
+        }
+        let c = &self.calibrated;
+        for (name, value, unit) in [
+            ("water", c.water_rate, "%"),
+            ("visceral fat", c.visceral_fat, "%"),
+            ("body fat", c.bf, "%"),
+            ("lean body mass", c.muscle_kg, "kg"),
+            ("bone mass", c.bone_mass, "kg"),
+        ] {
+            if let Some(value) = value {
+                writeln!(f, "    calibrated {}: {} {}", name, value, unit)?;
+            }
+        }
+        if let Some(trend) = &self.trend {
+            writeln!(f, "    {}", trend)?;
+        }
         Ok(())
     }
 }
 
-impl Person {
-    pub fn get_lbm_coefficient(&self, weight: f32, impedance: f32) -> f32 {
-        let mut lbm: f32 = (self.height * 9.058 / 100.0) * (self.height / 100.0);
-        lbm += weight * 0.32 + 12.226;
-        lbm -= impedance * 0.0068;
-        lbm -= self.age * 0.0542;
-
-        return lbm;
-    }
-
-    pub fn get_bmi(&self, weight: f32) -> f32 {
-        return weight / (((self.height * self.height) / 100.0) / 100.0);
-    }
-
-    pub fn get_muscle(&self, weight: f32, impedance: f32) -> f32 {
-        let mut muscle_mass: f32 = weight
-            - ((self.get_body_fat(weight, impedance) * 0.01) * weight)
-            - self.get_bone_mass(weight, impedance);
+/// Add full `years` to the date (Feb 29 becomes Feb 28 in non-leap years)
+fn add_years(date: NaiveDate, years: i32) -> NaiveDate {
+    let year = date.year() + years;
+    date.with_year(year)
+        .or_else(|| NaiveDate::from_ymd_opt(year, date.month(), date.day() - 1))
+        .unwrap_or(date)
+}
 
-        if self.sex == 0 && muscle_mass >= 84.0 {
-            muscle_mass = 120.0;
-        } else if self.sex == 1 && muscle_mass >= 93.5 {
-            muscle_mass = 120.0;
+impl Measurement {
+    /// The measurement as a sample of the trend analysis
+    pub fn sample(&self) -> Sample {
+        Sample {
+            date_time: self.date_time,
+            weight: self.weight,
+            fat: self.bf,
+            muscle: self.muscle_kg,
+            impedance: Some(self.impedance),
+            bmr: Some(self.bmr),
+            trend_weight: self.trend.map(|t| t.weight),
         }
-
-        return muscle_mass;
     }
+}
 
-    pub fn get_water(&self, weight: f32, impedance: f32) -> f32 {
-        let coeff: f32;
-        let water: f32 = (100.0 - self.get_body_fat(weight, impedance)) * 0.7;
-
-        if water < 50.0 {
-            coeff = 1.02;
-        } else {
-            coeff = 0.98;
+impl Person {
+    /// Exact age in years (with the fraction of the current year of life) on the given date
+    pub fn age_at(&self, date: NaiveDate) -> f32 {
+        let mut years = date.year() - self.birthday.year();
+        if add_years(self.birthday, years) > date {
+            years -= 1;
         }
+        let last = add_years(self.birthday, years);
+        let next = add_years(self.birthday, years + 1);
+        let fraction = (date - last).num_days() as f32 / (next - last).num_days() as f32;
 
-        return coeff * water;
+        years as f32 + fraction
     }
 
-    pub fn get_bone_mass(&self, weight: f32, impedance: f32) -> f32 {
-        let mut bone_mass: f32;
-        let base: f32;
-
-        if self.sex == 0 {
-            base = 0.245691014;
-        } else {
-            base = 0.18016894;
+    pub fn inputs(&self, date_time: NaiveDateTime, weight: f32, impedance: f32) -> Inputs {
+        Inputs {
+            sex: self.sex,
+            age: self.age_at(date_time.date()),
+            height: self.height,
+            weight,
+            impedance,
         }
+    }
 
-        bone_mass = (base - (self.get_lbm_coefficient(weight, impedance) * 0.05158)) * -1.0;
+    /// Comma-separated names of the configured algorithm sets
+    pub fn algorithm_names(&self) -> String {
+        self.algorithms
+            .iter()
+            .map(|a| a.name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
 
-        if bone_mass > 2.2 {
-            bone_mass += 0.1;
-        } else {
-            bone_mass -= 0.1;
-        }
+    /// Evaluate `formula` using the first algorithm set providing it
+    fn compute<F>(&self, i: &Inputs, formula: F) -> (f32, &'static str)
+    where
+        F: Fn(&dyn Algorithm, &Inputs) -> Option<f32>,
+    {
+        self.algorithms
+            .iter()
+            .find_map(|a| formula(*a, i).map(|v| (v, a.name())))
+            .unwrap_or_else(|| (formula(&MiFit, i).unwrap_or_default(), MiFit.name()))
+    }
 
-        if self.sex == 0 && bone_mass > 5.1 {
-            bone_mass = 8.0;
-        } else if self.sex == 1 && bone_mass > 5.2 {
-            bone_mass = 8.0;
-        }
+    /// Compute the whole body composition for the given scale reading,
+    /// including the confidence intervals of the values
+    pub fn measure(
+        &self,
+        date_time: NaiveDateTime,
+        weight: f32,
+        impedance: f32,
+        flags: u16,
+    ) -> Measurement {
+        let mut m = self.evaluate(date_time, weight, impedance, flags);
+        m.uncertainty = Some(self.uncertainty.propagate(&m, |weight, impedance| {
+            self.evaluate(date_time, weight, impedance, flags)
+        }));
+        m
+    }
 
-        return bone_mass;
+    /// Measurement without the impedance, with the body composition values taken as they are
+    /// from the `source` (the BMI, its rating and a missing BMR are computed)
+    pub fn measure_known(
+        &self,
+        date_time: NaiveDateTime,
+        weight: f32,
+        c: &Composition,
+        source: &'static str,
+    ) -> Measurement {
+        let mut m = self.evaluate(date_time, weight, 0.0, 0);
+        let src = |v: Option<f32>| if v.is_some() { source } else { "-" };
+        m.sources = Sources {
+            water_rate: src(c.water_rate),
+            bmr: if c.bmr.is_some() {
+                source
+            } else {
+                m.sources.bmr
+            },
+            visceral_fat: src(c.visceral_fat),
+            bf: src(c.bf),
+            muscle: src(c.muscle_kg),
+            skeletal_muscle: "-",
+            bone_mass: src(c.bone_mass),
+        };
+        m.water_rate = c.water_rate;
+        m.bmr = c.bmr.unwrap_or(m.bmr);
+        m.visceral_fat = c.visceral_fat;
+        m.bf = c.bf;
+        m.muscle_kg = c.muscle_kg;
+        m.muscle_rate = c.muscle_kg.map(|kg| (100.0 / weight) * kg);
+        m.skeletal_muscle_kg = None;
+        m.bone_mass = c.bone_mass;
+        m.fat_rating =
+            c.bf.filter(|_| m.age >= ADULT_AGE)
+                .map(|bf| rate_body_fat(bf, self.sex, m.age));
+        m
     }
 
-    pub fn get_visceral_fat(&self, weight: f32) -> f32 {
-        let visceral_fat: f32;
-        if self.sex == 0 {
-            if weight > (13.0 - (self.height * 0.5)) * -1.0 {
-                let subsubcalc: f32 =
-                    ((self.height * 1.45) + (self.height * 0.1158) * self.height) - 120.0;
-                let subcalc: f32 = weight * 500.0 / subsubcalc;
-                visceral_fat = (subcalc - 6.0) + (self.age * 0.07);
+    fn evaluate(
+        &self,
+        date_time: NaiveDateTime,
+        weight: f32,
+        impedance: f32,
+        flags: u16,
+    ) -> Measurement {
+        let i = self.inputs(date_time, weight, impedance);
+        let bmi = i.bmi();
+        let mut warnings = vec![];
+        let adult = i.age >= ADULT_AGE;
+
+        // no adult body composition estimates for children and adolescents
+        let adult_only = |formula: &dyn Fn(&dyn Algorithm, &Inputs) -> Option<f32>| {
+            if adult {
+                let (value, src) = self.compute(&i, formula);
+                (Some(value), src)
             } else {
-                let subcalc: f32 = 0.691 + (self.height * -0.0024) + (self.height * -0.0024);
-                visceral_fat = (((self.height * 0.027) - (subcalc * weight)) * -1.0)
-                    + (self.age * 0.07)
-                    - self.age;
+                (None, "-")
             }
+        };
+        let (bf, bf_src) = adult_only(&|a, i| a.body_fat(i));
+        // the water and muscle are consistent with the body fat of whichever set provided it
+        let selected_bf = bf.unwrap_or_default();
+        let (water_rate, water_src) = adult_only(&|a, i| a.water(i, selected_bf));
+        let (visceral_fat, visceral_src) = adult_only(&|a, i| a.visceral_fat(i));
+        let (muscle_kg, muscle_src) = adult_only(&|a, i| a.muscle(i, selected_bf));
+        let (bone_mass, bone_src) = adult_only(&|a, i| a.bone_mass(i));
+        let (skeletal_muscle_kg, skeletal_muscle_src) = self
+            .algorithms
+            .iter()
+            .filter(|_| adult)
+            .find_map(|a| a.skeletal_muscle(&i).map(|v| (Some(v), a.name())))
+            .unwrap_or((None, "-"));
+        let (bmr, bmr_src) = if adult {
+            self.compute(&i, |a, i| a.bmr(i))
         } else {
-            if self.height < weight * 1.6 {
-                let subcalc: f32 =
-                    ((self.height * 0.4) - (self.height * (self.height * 0.0826))) * -1.0;
-                visceral_fat = ((weight * 305.0) / (subcalc + 48.0)) - 2.9 + (self.age * 0.15);
-            } else {
-                let subcalc: f32 = 0.765 + self.height * -0.0015;
-                visceral_fat =
-                    (((self.height * 0.143) - (weight * subcalc)) * -1.0) + (self.age * 0.15) - 5.0;
+            (schofield_bmr(weight, i.sex, i.age), "schofield")
+        };
+
+        let bmi_for_age = if adult {
+            None
+        } else {
+            bmi_for_age(bmi, i.sex, i.age)
+        };
+        if !adult {
+            warnings.push(format!(
+                "age {:.1}: the body composition is not estimated under {} years",
+                i.age, ADULT_AGE
+            ));
+            if bmi_for_age.is_none() {
+                warnings.push("no BMI-for-age reference for this age".to_string());
             }
+        } else if i.age > VALIDATED_MAX_AGE {
+            warnings.push(format!(
+                "age {:.1}: the formulas are validated for {}-{} years only",
+                i.age, ADULT_AGE, VALIDATED_MAX_AGE
+            ));
         }
 
-        return visceral_fat;
+        Measurement {
+            date_time,
+            weight,
+            impedance,
+            flags,
+            age: i.age,
+            bmi,
+            water_rate,
+            bmr,
+            visceral_fat,
+            bf,
+            muscle_kg,
+            muscle_rate: muscle_kg.map(|kg| (100.0 / weight) * kg), // convert muscle in kg to percent
+            skeletal_muscle_kg,
+            bone_mass,
+            sources: Sources {
+                water_rate: water_src,
+                bmr: bmr_src,
+                visceral_fat: visceral_src,
+                bf: bf_src,
+                muscle: muscle_src,
+                skeletal_muscle: skeletal_muscle_src,
+                bone_mass: bone_src,
+            },
+            bmi_rating: adult.then(|| rate_bmi(bmi, i.age)),
+            bmi_for_age,
+            fat_rating: bf.map(|bf| rate_body_fat(bf, i.sex, i.age)),
+            warnings,
+            trend: None,
+            calibrated: Calibrated::default(),
+            uncertainty: None,
+        }
     }
+}
 
-    pub fn get_body_fat(&self, weight: f32, impedance: f32) -> f32 {
-        let mut body_fat: f32;
-        let mut lbm_sub: f32 = 0.8;
-
-        if self.sex == 0 && self.age <= 49.0 {
-            lbm_sub = 9.25;
-        } else if self.sex == 0 && self.age > 49.0 {
-            lbm_sub = 7.25;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Profile `test` with the default settings, also used by the tests of the other modules
+    pub(crate) fn person(birthday: NaiveDate) -> Person {
+        Person {
+            name: "test".to_string(),
+            sex: 1,
+            birthday,
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
         }
+    }
 
-        let lbm_coeff: f32 = self.get_lbm_coefficient(weight, impedance);
-        let mut coeff: f32 = 1.0;
-
-        if self.sex == 1 && weight < 61.0 {
-            coeff = 0.98;
-        } else if self.sex == 0 && weight > 60.0 {
-            coeff = 0.96;
-
-            if self.height > 160.0 {
-                coeff *= 1.03;
-            }
-        } else if self.sex == 0 && weight < 50.0 {
-            coeff = 1.02;
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
 
-            if self.height > 160.0 {
-                coeff *= 1.03;
-            }
-        }
+    #[test]
+    fn age_at_birthday() {
+        let p = person(date(1990, 6, 15));
+        assert_eq!(p.age_at(date(2020, 6, 15)), 30.0);
+        assert!(p.age_at(date(2020, 6, 14)) < 30.0);
+        assert!(p.age_at(date(2020, 6, 14)) > 29.99);
+        assert_eq!(p.age_at(date(2020, 6, 16)), 30.0 + 1.0 / 365.0);
+        assert_eq!(p.age_at(date(1990, 6, 15)), 0.0);
+    }
 
-        body_fat = (1.0 - (((lbm_coeff - lbm_sub) * coeff) / weight)) * 100.0;
+    #[test]
+    fn age_at_leap_day_birthday() {
+        let p = person(date(2000, 2, 29));
+        // the birthday is on 28 February in the common years
+        assert_eq!(p.age_at(date(2023, 2, 28)), 23.0);
+        assert!(p.age_at(date(2023, 2, 27)) < 23.0);
+        assert_eq!(p.age_at(date(2024, 2, 29)), 24.0);
+        assert!(p.age_at(date(2024, 2, 28)) < 24.0);
+        assert!(p.age_at(date(2024, 3, 1)) > 24.0);
+    }
 
-        if body_fat > 63.0 {
-            body_fat = 75.0;
-        }
+    #[test]
+    fn age_at_leap_year() {
+        let p = person(date(1990, 3, 1));
+        // 366 days from 1 March 2023 to 1 March 2024
+        assert_eq!(p.age_at(date(2024, 2, 29)), 33.0 + 365.0 / 366.0);
+        assert_eq!(p.age_at(date(2024, 3, 1)), 34.0);
+    }
 
-        return body_fat;
+    fn at(y: i32, m: u32, d: u32) -> NaiveDateTime {
+        date(y, m, d).and_hms_opt(8, 0, 0).unwrap()
     }
 
-    pub fn get_bmr(&self, weight: f32) -> f32 {
-        let mut bmr: f32;
+    #[test]
+    fn water_and_muscle_follow_selected_body_fat() {
+        let mut p = person(date(1990, 6, 15));
+        p.algorithms = vec![&crate::algorithm::Sun];
+        let m = p.measure(at(2020, 6, 15), 80.0, 500.0, 0);
+        let i = p.inputs(at(2020, 6, 15), 80.0, 500.0);
+        let bf = crate::algorithm::Sun.body_fat(&i).unwrap();
+
+        assert_eq!(m.bf, Some(bf));
+        assert_eq!(m.sources.bf, "sun");
+        assert_eq!(m.water_rate, Some(MiFit.get_water(bf)));
+        assert_eq!(m.muscle_kg, Some(MiFit.get_muscle(&i, bf)));
+        assert_ne!(m.bf, Some(MiFit.get_body_fat(&i)));
+    }
 
-        if self.sex == 0 {
-            //female
-            bmr = 864.6 + weight * 10.2036;
-            bmr -= self.height * 0.39336;
-            bmr -= self.age * 6.204;
-        } else {
-            bmr = 877.8 + weight * 14.916;
-            bmr -= self.height * 0.726;
-            bmr -= self.age * 8.976;
-        }
+    #[test]
+    fn sources_parsed_back() {
+        let mut p = person(date(1990, 6, 15));
+        p.algorithms = vec![&crate::algorithm::Janssen];
+        let m = p.measure(at(2020, 6, 15), 80.0, 500.0, 0);
+        assert_eq!(
+            Sources::parse(&m.sources.to_string()).to_string(),
+            m.sources.to_string()
+        );
+        let legacy = Sources::parse("mifit");
+        assert_eq!(legacy.bf, "mifit");
+        assert_eq!(legacy.skeletal_muscle, "mifit");
+        let partial = Sources::parse("water=other,fat=sun");
+        assert_eq!(partial.water_rate, "stored");
+        assert_eq!(partial.bf, "sun");
+        assert_eq!(partial.skeletal_muscle, "-");
+    }
 
-        return bmr;
+    #[test]
+    fn skeletal_muscle_kept_apart_from_lean_mass() {
+        let mut p = person(date(1990, 6, 15));
+        let m = p.measure(at(2020, 6, 15), 80.0, 500.0, 0);
+        assert_eq!(m.skeletal_muscle_kg, None);
+        assert_eq!(m.sources.skeletal_muscle, "-");
+
+        p.algorithms = vec![&crate::algorithm::Janssen];
+        let janssen = p.measure(at(2020, 6, 15), 80.0, 500.0, 0);
+        assert_eq!(janssen.sources.muscle, "mifit");
+        assert_eq!(janssen.muscle_kg, m.muscle_kg);
+        assert_eq!(janssen.sources.skeletal_muscle, "janssen");
+        let skeletal = janssen.skeletal_muscle_kg.unwrap();
+        assert!(skeletal > 20.0 && skeletal < janssen.muscle_kg.unwrap());
     }
 }
@@ -0,0 +1,272 @@
+use crate::body::Measurement;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::fmt;
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
+
+/// Fewest reference measurements for fitting the slope, fewer give an offset only
+pub const MIN_SLOPE_POINTS: usize = 3;
+
+/// Plausible range of the fitted slope, a steeper or flatter fit is clamped
+pub const SLOPE_RANGE: (f32, f32) = (0.5, 2.0);
+
+/// Calibrated metric
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Display, EnumString, EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum Metric {
+    /// body fat (%)
+    Fat,
+    /// muscle mass (kg)
+    Muscle,
+    /// body water (%)
+    Water,
+    /// bone mass (kg)
+    Bone,
+    /// visceral fat (rating)
+    VisceralFat,
+}
+
+impl Metric {
+    /// Column of the `mifit` table with the (uncalibrated) scale value
+    pub fn column(&self) -> &'static str {
+        match self {
+            Metric::Fat => "fat_rate",
+            Metric::Muscle => "muscle_kg",
+            Metric::Water => "body_water_rate",
+            Metric::Bone => "bone_mass",
+            Metric::VisceralFat => "visceral_fat",
+        }
+    }
+
+    /// Smallest spread (standard deviation) of the scale values for fitting the slope
+    pub fn min_spread(&self) -> f32 {
+        match self {
+            Metric::Bone => 0.2,
+            _ => 1.0,
+        }
+    }
+
+    /// Range of the valid values
+    fn bounds(&self) -> (f32, f32) {
+        match self {
+            Metric::Fat | Metric::Water => (0.0, 100.0),
+            _ => (0.0, f32::MAX),
+        }
+    }
+
+    fn value(&self, m: &Measurement) -> Option<f32> {
+        match self {
+            Metric::Fat => m.bf,
+            Metric::Muscle => m.muscle_kg,
+            Metric::Water => m.water_rate,
+            Metric::Bone => m.bone_mass,
+            Metric::VisceralFat => m.visceral_fat,
+        }
+    }
+}
+
+/// Reference measurement of a metric (eg. DEXA or caliper body fat)
+pub struct Reference {
+    pub id: i32,
+    pub date: NaiveDate,
+    pub metric: Metric,
+    pub value: f32,
+    pub source: String,
+}
+
+/// Linear correction `reference = offset + slope * scale`
+#[derive(Clone, Copy, Debug)]
+pub struct Correction {
+    pub offset: f32,
+    pub slope: f32,
+    /// number of the reference measurements used
+    pub points: usize,
+}
+
+impl Correction {
+    /// Least-squares fit of the (scale, reference) pairs.
+    ///
+    /// The slope needs at least [`MIN_SLOPE_POINTS`] pairs with the scale values spread
+    /// by `min_spread` (standard deviation), otherwise only the offset is fitted.
+    /// The slope is clamped to [`SLOPE_RANGE`].
+    pub fn fit(pairs: &[(f32, f32)], min_spread: f32) -> Option<Correction> {
+        if pairs.is_empty() {
+            return None;
+        }
+        let n = pairs.len() as f32;
+        let mean_x = pairs.iter().map(|p| p.0).sum::<f32>() / n;
+        let mean_y = pairs.iter().map(|p| p.1).sum::<f32>() / n;
+        let sxx: f32 = pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f32 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+
+        let spread = (sxx / n).sqrt();
+        let slope = if pairs.len() >= MIN_SLOPE_POINTS && spread >= min_spread {
+            (sxy / sxx).clamp(SLOPE_RANGE.0, SLOPE_RANGE.1)
+        } else {
+            1.0
+        };
+        Some(Correction {
+            offset: mean_y - slope * mean_x,
+            slope,
+            points: pairs.len(),
+        })
+    }
+
+    pub fn apply(&self, value: f32) -> f32 {
+        self.offset + self.slope * value
+    }
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:+.3} + {:.3} × scale ({} references)",
+            self.offset, self.slope, self.points
+        )
+    }
+}
+
+/// Calibrated values of a measurement (`None` where no correction is fitted)
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct Calibrated {
+    pub bf: Option<f32>,
+    pub muscle_kg: Option<f32>,
+    pub water_rate: Option<f32>,
+    pub bone_mass: Option<f32>,
+    pub visceral_fat: Option<f32>,
+}
+
+/// Per-profile corrections of the metrics
+#[derive(Clone, Default)]
+pub struct Calibration {
+    pub corrections: Vec<(Metric, Correction)>,
+}
+
+impl Calibration {
+    /// Fit the corrections from (metric, scale value, reference value) triples
+    pub fn fit(pairs: &[(Metric, f32, f32)]) -> Calibration {
+        Calibration {
+            corrections: Metric::iter()
+                .filter_map(|metric| {
+                    let points: Vec<(f32, f32)> = pairs
+                        .iter()
+                        .filter(|p| p.0 == metric)
+                        .map(|p| (p.1, p.2))
+                        .collect();
+                    Correction::fit(&points, metric.min_spread()).map(|c| (metric, c))
+                })
+                .collect(),
+        }
+    }
+
+    fn correct(&self, metric: Metric, m: &Measurement) -> Option<f32> {
+        let (min, max) = metric.bounds();
+        self.corrections
+            .iter()
+            .find(|c| c.0 == metric)
+            .and_then(|c| metric.value(m).map(|v| c.1.apply(v).clamp(min, max)))
+    }
+
+    /// Calibrated values of the measurement
+    pub fn apply(&self, m: &Measurement) -> Calibrated {
+        Calibrated {
+            bf: self.correct(Metric::Fat, m),
+            muscle_kg: self.correct(Metric::Muscle, m),
+            water_rate: self.correct(Metric::Water, m),
+            bone_mass: self.correct(Metric::Bone, m),
+            visceral_fat: self.correct(Metric::VisceralFat, m),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Person;
+    use crate::outlier::OutlierFilter;
+    use crate::uncertainty::InputUncertainty;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn offset_only_with_few_references() {
+        let c = Correction::fit(&[(20.0, 23.0)], 1.0).unwrap();
+        assert_eq!((c.offset, c.slope, c.points), (3.0, 1.0, 1));
+        // two references never give a slope
+        let c = Correction::fit(&[(20.0, 23.0), (30.0, 31.0)], 1.0).unwrap();
+        assert_eq!(c.slope, 1.0);
+        assert!(close(c.offset, 2.0));
+        assert!(Correction::fit(&[], 1.0).is_none());
+    }
+
+    #[test]
+    fn offset_only_without_spread() {
+        let c = Correction::fit(&[(20.0, 22.0), (20.5, 24.0), (20.2, 23.0)], 1.0).unwrap();
+        assert_eq!(c.slope, 1.0);
+        assert!(close(c.apply(20.0), 22.767));
+    }
+
+    #[test]
+    fn slope_with_spread() {
+        let pairs: Vec<(f32, f32)> = [15.0, 20.0, 25.0, 30.0]
+            .iter()
+            .map(|&x| (x, 2.0 + 0.9 * x))
+            .collect();
+        let c = Correction::fit(&pairs, 1.0).unwrap();
+        assert!(close(c.slope, 0.9));
+        assert!(close(c.offset, 2.0));
+        assert!(close(c.apply(10.0), 11.0));
+    }
+
+    #[test]
+    fn slope_clamped() {
+        let pairs = [(18.0, 10.0), (20.0, 30.0), (22.0, 50.0)];
+        let c = Correction::fit(&pairs, 1.0).unwrap();
+        assert_eq!(c.slope, SLOPE_RANGE.1);
+        // still through the mean of the pairs
+        assert!(close(c.apply(20.0), 30.0));
+        let pairs = [(18.0, 20.0), (20.0, 20.0), (22.0, 20.1)];
+        assert_eq!(Correction::fit(&pairs, 1.0).unwrap().slope, SLOPE_RANGE.0);
+    }
+
+    #[test]
+    fn calibrated_values_within_bounds() {
+        let p = Person {
+            name: "test".to_string(),
+            sex: 1,
+            birthday: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
+        };
+        let date_time = NaiveDate::from_ymd_opt(2023, 1, 1)
+            .unwrap()
+            .and_hms_opt(7, 0, 0)
+            .unwrap();
+        let m = p.measure(date_time, 80.0, 500.0, 0);
+        let calibration = Calibration::fit(&[
+            (Metric::Fat, m.bf.unwrap(), m.bf.unwrap() - 50.0),
+            (
+                Metric::Water,
+                m.water_rate.unwrap(),
+                m.water_rate.unwrap() + 60.0,
+            ),
+            (
+                Metric::Bone,
+                m.bone_mass.unwrap(),
+                m.bone_mass.unwrap() + 0.5,
+            ),
+        ]);
+        let c = calibration.apply(&m);
+        assert_eq!(c.bf, Some(0.0));
+        assert_eq!(c.water_rate, Some(100.0));
+        assert!(close(c.bone_mass.unwrap(), m.bone_mass.unwrap() + 0.5));
+        assert_eq!(c.muscle_kg, None);
+    }
+}
@@ -0,0 +1,475 @@
+use crate::algorithm;
+use crate::body::Person;
+use crate::database::{Database, Tls, DEFAULT_CONNECT_TIMEOUT};
+use crate::energy::ActivityLevel;
+use crate::goal::{Goal, GoalDirection, GoalMetric};
+use crate::mqtt::Mqtt;
+use crate::omh::Device;
+use crate::outlier::OutlierFilter;
+use crate::sink::{CsvLog, FitDir, JsonLines, Sink, SinkSpool, StorageSink};
+use crate::spool::Spool;
+use crate::sqlite::Sqlite;
+use crate::storage::Storage;
+use crate::uncertainty::InputUncertainty;
+use chrono::NaiveDate;
+use ini::ini::Properties;
+use ini::Ini;
+use rumqttc::MqttOptions;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Read the secret `key` of the section: the value itself (taken literally),
+/// the environment variable named by `{key}_env` or the content of the `{key}_file`.
+///
+/// A relative `{key}_file` is looked up in the `$CREDENTIALS_DIRECTORY` of systemd
+/// (`LoadCredential=`) when it is set.
+fn config_read_secret(
+    section: &Properties,
+    key: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let env_key = format!("{}_env", key);
+    let file_key = format!("{}_file", key);
+    match (
+        section.get(key),
+        section.get(&env_key),
+        section.get(&file_key),
+    ) {
+        (Some(value), None, None) => Ok(Some(value.to_string())),
+        (None, Some(name), None) => {
+            let secret = env::var(name)
+                .map_err(|e| format!("`{}`: environment variable `{}`: {}", env_key, name, e))?;
+            Ok(Some(secret))
+        }
+        (None, None, Some(path)) => {
+            let mut path = PathBuf::from(path);
+            if path.is_relative() {
+                if let Some(dir) = env::var_os("CREDENTIALS_DIRECTORY") {
+                    path = Path::new(&dir).join(path);
+                }
+            }
+            let secret = fs::read_to_string(&path)
+                .map_err(|e| format!("`{}`: cannot read {}: {}", file_key, path.display(), e))?;
+            Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+        }
+        (None, None, None) => Ok(None),
+        _ => Err(format!(
+            "set only one of `{}`, `{}` and `{}`",
+            key, env_key, file_key
+        )
+        .into()),
+    }
+}
+
+/// Connections kept open to the database by default
+const DEFAULT_POOL_SIZE: usize = 2;
+
+/// Split the `host` value: `host`, `host:port`, an IPv6 address (`::1`),
+/// `[address]:port` or the directory of the Unix socket (`/run/postgresql`)
+fn split_host_port(host: &str) -> Result<(&str, Option<u16>), Box<dyn std::error::Error>> {
+    if let Some(bracketed) = host.strip_prefix('[') {
+        let (address, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| format!("invalid `host`: {}", host))?;
+        return match rest.strip_prefix(':') {
+            Some(port) => Ok((address, Some(port.parse()?))),
+            None if rest.is_empty() => Ok((address, None)),
+            None => Err(format!("invalid `host`: {}", host).into()),
+        };
+    }
+    match host.split_once(':') {
+        Some((name, port)) if !host.starts_with('/') && !port.contains(':') => {
+            Ok((name, Some(port.parse()?)))
+        }
+        _ => Ok((host, None)),
+    }
+}
+
+/// Read the `[postgres]` section
+pub fn config_read_postgres(conf: Ini) -> Result<Database, Box<dyn std::error::Error>> {
+    match conf.section(Some("postgres".to_owned())) {
+        Some(section) => {
+            let mut config = tokio_postgres::Config::new();
+            let host = section.get("host").ok_or("missing `host`")?;
+            let (host, port) = split_host_port(host)?;
+            config.host(host);
+            let port = match (port, section.get("port")) {
+                (Some(port), _) => Some(port),
+                (None, Some(port)) => Some(port.parse()?),
+                (None, None) => None,
+            };
+            if let Some(port) = port {
+                config.port(port);
+            }
+            config
+                .dbname(section.get("dbname").ok_or("missing `dbname`")?)
+                .user(section.get("username").ok_or("missing `username`")?)
+                .application_name(
+                    section
+                        .get("application_name")
+                        .map_or("bluescale", |name| name.as_str()),
+                );
+            //short, the measurements are spooled while the database is unreachable
+            config.connect_timeout(match section.get("connect_timeout") {
+                Some(secs) => Duration::from_secs(secs.parse()?),
+                None => DEFAULT_CONNECT_TIMEOUT,
+            });
+            //not needed eg. for the peer authentication over the Unix socket
+            if let Some(password) = config_read_secret(section, "password")? {
+                config.password(password);
+            }
+
+            let mut tls = Tls::default();
+            if let Some(mode) = section.get("sslmode") {
+                tls.mode = mode
+                    .parse()
+                    .map_err(|_| format!("invalid `sslmode`: {}", mode))?;
+            }
+            tls.root_cert = section.get("sslrootcert").map(PathBuf::from);
+            tls.client_cert = match (section.get("sslcert"), section.get("sslkey")) {
+                (Some(cert), Some(key)) => Some((PathBuf::from(cert), PathBuf::from(key))),
+                (None, None) => None,
+                _ => Err("`sslcert` and `sslkey` have to be set together")?,
+            };
+
+            let pool_size = match section.get("pool_size") {
+                Some(size) => size.parse()?,
+                None => DEFAULT_POOL_SIZE,
+            };
+            let mut db = Database::new("🦏 postgres", config, &tls, pool_size)?;
+            //apply the pending schema migrations at startup
+            if let Some(migrate) = section.get("auto_migrate") {
+                db.auto_migrate = migrate.parse()?;
+            }
+            Ok(db)
+        }
+        None => Err("missing [postgres] config section")?,
+    }
+}
+
+/// Read the `[sqlite]` section
+pub fn config_read_sqlite(conf: Ini) -> Result<Sqlite, Box<dyn std::error::Error>> {
+    match conf.section(Some("sqlite".to_owned())) {
+        Some(section) => {
+            let mut db = Sqlite::open(
+                "🪶 sqlite",
+                Path::new(section.get("path").ok_or("missing `path`")?),
+            )?;
+            //apply the pending schema migrations at startup
+            if let Some(migrate) = section.get("auto_migrate") {
+                db.auto_migrate = migrate.parse()?;
+            }
+            Ok(db)
+        }
+        None => Err("missing [sqlite] config section")?,
+    }
+}
+
+/// Read the storage config: the `[sqlite]` or the `[postgres]` section
+pub fn config_read_storage(conf: Ini) -> Result<Box<dyn Storage>, Box<dyn std::error::Error>> {
+    let sqlite = conf.section(Some("sqlite".to_owned())).is_some();
+    let postgres = conf.section(Some("postgres".to_owned())).is_some();
+    match (sqlite, postgres) {
+        (true, true) => Err("configure either the [sqlite] or the [postgres] section")?,
+        (true, false) => match config_read_sqlite(conf) {
+            Ok(db) => Ok(Box::new(db)),
+            Err(e) => Err(format!("[sqlite]: {}", e).into()),
+        },
+        (false, _) => match config_read_postgres(conf) {
+            Ok(db) => Ok(Box::new(db)),
+            Err(e) => Err(format!("[postgres]: {}", e).into()),
+        },
+    }
+}
+
+/// Read the `[profile]` section
+pub fn config_read_profile(conf: Ini) -> Result<Person, Box<dyn std::error::Error>> {
+    match conf.section(Some("profile".to_owned())) {
+        Some(section) => {
+            let date_str = section.get("birthday").ok_or("missing `birthday`")?;
+            let birthday = match NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+                Ok(d) => d,
+                Err(e) => return Err(format!("error parsing `birthday`: {}", e).into()),
+            };
+
+            //algorithm sets in order of preference, eg. `sun, janssen`
+            let mut algorithms = vec![];
+            for name in section
+                .get("algorithm")
+                .map(|s| s.as_str())
+                .unwrap_or("mifit")
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+            {
+                match algorithm::find(name) {
+                    Some(a) => algorithms.push(a),
+                    None => return Err(format!("unknown `algorithm`: {}", name).into()),
+                }
+            }
+
+            let mut goals = vec![];
+            for metric in [GoalMetric::Weight, GoalMetric::Fat] {
+                let key = format!("goal_{}", metric);
+                if let Some(target) = section.get(&key) {
+                    let target = target
+                        .parse()
+                        .map_err(|e| format!("error parsing `{}`: {}", key, e))?;
+                    let key = format!("goal_{}_direction", metric);
+                    // required, a default would report a goal on the other side as reached
+                    let direction: GoalDirection = section
+                        .get(&key)
+                        .ok_or(format!("missing `{}` (`lose` or `gain`)", key))?
+                        .parse()
+                        .map_err(|_| format!("`{}` must be `lose` or `gain`", key))?;
+                    goals.push(Goal {
+                        metric,
+                        target,
+                        direction,
+                    });
+                }
+            }
+
+            let mut outlier = OutlierFilter::default();
+            for (key, value) in [
+                ("max_weight_change", &mut outlier.max_weight_change),
+                (
+                    "max_weight_change_per_day",
+                    &mut outlier.max_weight_change_per_day,
+                ),
+                ("max_impedance_change", &mut outlier.max_impedance_change),
+            ] {
+                if let Some(v) = section.get(key) {
+                    *value = match v.parse() {
+                        Ok(v) => v,
+                        Err(e) => return Err(format!("error parsing `{}`: {}", key, e).into()),
+                    };
+                }
+            }
+
+            //activity level name (eg. `moderate`) or the factor itself
+            let activity = match section.get("activity") {
+                Some(a) => match a.parse::<ActivityLevel>() {
+                    Ok(level) => level.factor(),
+                    Err(_) => match a.parse() {
+                        Ok(factor) => factor,
+                        Err(e) => return Err(format!("error parsing `activity`: {}", e).into()),
+                    },
+                },
+                None => ActivityLevel::Sedentary.factor(),
+            };
+
+            Ok(Person {
+                name: section
+                    .get("name")
+                    .cloned()
+                    .unwrap_or_else(|| "default".to_string()),
+                sex: section.get("sex").ok_or("missing `sex`")?.parse()?,
+                birthday,
+                height: section.get("height").ok_or("missing `height`")?.parse()?,
+                algorithms,
+                goals,
+                outlier,
+                activity,
+                uncertainty: Default::default(),
+            })
+        }
+        None => Err("missing [profile] config section")?,
+    }
+}
+
+/// Read the optional `[uncertainty]` section
+pub fn config_read_uncertainty(conf: Ini) -> Result<InputUncertainty, Box<dyn std::error::Error>> {
+    let mut uncertainty = InputUncertainty::default();
+    if let Some(section) = conf.section(Some("uncertainty".to_owned())) {
+        if let Some(weight) = section.get("weight") {
+            uncertainty.weight = weight.parse()?;
+        }
+        if let Some(impedance) = section.get("impedance") {
+            uncertainty.impedance = impedance.parse()?;
+        }
+    }
+    Ok(uncertainty)
+}
+
+/// Default directory of the spool
+const DEFAULT_SPOOL_PATH: &str = "/var/spool/bluescale";
+
+/// Read the optional `[spool]` section
+pub fn config_read_spool(conf: Ini) -> Result<Spool, Box<dyn std::error::Error>> {
+    let path = conf
+        .section(Some("spool".to_owned()))
+        .and_then(|section| section.get("path"))
+        .map(|path| path.as_str())
+        .unwrap_or(DEFAULT_SPOOL_PATH);
+    Ok(Spool::new(Path::new(path)))
+}
+
+/// Sinks of the measurements: the storage and the optional `[csv_log]` section,
+/// each with its spool (the storage in the `spool` directory, the others in its subdirectories)
+pub fn config_read_sinks(
+    conf: Ini,
+    db: Rc<dyn Storage>,
+    spool: &Spool,
+) -> Result<Vec<SinkSpool>, Box<dyn std::error::Error>> {
+    let mut sinks = vec![SinkSpool {
+        sink: Box::new(StorageSink::new(db)),
+        spool: spool.clone(),
+    }];
+
+    if let Some(section) = conf.section(Some("csv_log".to_owned())) {
+        let path = section.get("path").ok_or("[csv_log]: missing `path`")?;
+        let sink = CsvLog {
+            path: PathBuf::from(path),
+        };
+        sinks.push(SinkSpool {
+            spool: spool.subdir(sink.name()),
+            sink: Box::new(sink),
+        });
+    }
+
+    if let Some(section) = conf.section(Some("garmin_fit".to_owned())) {
+        let path = section.get("path").ok_or("[garmin_fit]: missing `path`")?;
+        let sink = FitDir {
+            path: PathBuf::from(path),
+        };
+        sinks.push(SinkSpool {
+            spool: spool.subdir(sink.name()),
+            sink: Box::new(sink),
+        });
+    }
+
+    if let Some(section) = conf.section(Some("mqtt".to_owned())) {
+        let sink = config_read_mqtt(section).map_err(|e| format!("[mqtt]: {}", e))?;
+        sinks.push(SinkSpool {
+            spool: spool.subdir(sink.name()),
+            sink: Box::new(sink),
+        });
+    }
+
+    if let Some(section) = conf.section(Some("json_lines".to_owned())) {
+        let path = section.get("path").ok_or("[json_lines]: missing `path`")?;
+        let address = conf
+            .section(Some("miscale".to_owned()))
+            .and_then(|s| s.get("mac"))
+            .map(String::from);
+        let sink = JsonLines {
+            path: (path != "-").then(|| PathBuf::from(path)),
+            device: Device::new(address),
+        };
+        sinks.push(SinkSpool {
+            spool: spool.subdir(sink.name()),
+            sink: Box::new(sink),
+        });
+    }
+
+    Ok(sinks)
+}
+
+/// Read the `[mqtt]` section
+fn config_read_mqtt(section: &Properties) -> Result<Mqtt, Box<dyn std::error::Error>> {
+    let host = section.get("host").ok_or("missing `host`")?;
+    let port = match section.get("port") {
+        Some(port) => port.parse()?,
+        None => 1883,
+    };
+    let client_id = section
+        .get("client_id")
+        .map_or("bluescale", |id| id.as_str());
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    match (
+        section.get("username"),
+        config_read_secret(section, "password")?,
+    ) {
+        (Some(username), password) => {
+            options.set_credentials(username, password.unwrap_or_default());
+        }
+        (None, Some(_)) => return Err("`password` needs the `username`".into()),
+        (None, None) => (),
+    }
+
+    let topic = section.get("topic").map_or("bluescale", |t| t.as_str());
+    let discovery = match section.get("discovery") {
+        Some(discovery) => discovery.parse()?,
+        None => true,
+    };
+    let discovery_prefix = section
+        .get("discovery_prefix")
+        .map_or("homeassistant", |p| p.as_str());
+
+    Ok(Mqtt::new(
+        options,
+        topic.trim_end_matches('/').to_string(),
+        discovery.then(|| discovery_prefix.trim_end_matches('/').to_string()),
+    ))
+}
+
+/// Is the JSON Lines sink writing to the standard output (`path=-`)
+pub fn config_json_lines_stdout(conf: &Ini) -> bool {
+    conf.section(Some("json_lines".to_owned()))
+        .and_then(|section| section.get("path"))
+        .is_some_and(|path| path == "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_and_port() {
+        let split = |host| split_host_port(host).unwrap();
+        assert_eq!(split("localhost"), ("localhost", None));
+        assert_eq!(split("192.168.1.1:5433"), ("192.168.1.1", Some(5433)));
+        assert_eq!(split("/run/postgresql"), ("/run/postgresql", None));
+        assert_eq!(split("::1"), ("::1", None));
+        assert_eq!(split("fe80::1:2"), ("fe80::1:2", None));
+        assert_eq!(split("[::1]"), ("::1", None));
+        assert_eq!(split("[::1]:5433"), ("::1", Some(5433)));
+        assert!(split_host_port("[::1").is_err());
+        assert!(split_host_port("[::1]5433").is_err());
+        assert!(split_host_port("localhost:port").is_err());
+    }
+
+    #[test]
+    fn secrets() {
+        env::set_var("BLUESCALE_TEST_SECRET", "s3cret");
+        let secret = |conf: &str| {
+            let conf = Ini::load_from_str(&format!("[postgres]\n{}", conf)).unwrap();
+            config_read_secret(conf.section(Some("postgres")).unwrap(), "password")
+        };
+        // only the values marked as the environment variables are expanded
+        assert_eq!(
+            secret("password = pa${ss}").unwrap().as_deref(),
+            Some("pa${ss}")
+        );
+        assert_eq!(
+            secret("password_env = BLUESCALE_TEST_SECRET")
+                .unwrap()
+                .as_deref(),
+            Some("s3cret")
+        );
+        assert!(secret("password_env = BLUESCALE_TEST_UNSET").is_err());
+        assert!(secret("password = a\npassword_env = BLUESCALE_TEST_SECRET").is_err());
+        assert_eq!(secret("user = a").unwrap(), None);
+    }
+
+    #[test]
+    fn goal_direction_required() {
+        let profile = |goals: &str| {
+            let conf = format!(
+                "[profile]\nsex = 1\nbirthday = 1990-01-01\nheight = 180\n{}",
+                goals
+            );
+            config_read_profile(Ini::load_from_str(&conf).unwrap())
+        };
+        assert!(profile("goal_weight = 75").is_err());
+        assert!(profile("goal_weight = 75\ngoal_weight_direction = up").is_err());
+        let p = profile("goal_weight = 85\ngoal_weight_direction = gain").unwrap();
+        assert_eq!(p.goals[0].metric, GoalMetric::Weight);
+        assert_eq!(p.goals[0].direction, GoalDirection::Gain);
+        assert!(profile("").unwrap().goals.is_empty());
+    }
+}
@@ -1,41 +1,668 @@
-use crate::body::Measurement;
-use crate::body::Person;
-use postgres::{Client, NoTls};
+use crate::body::{Composition, Measurement, Person};
+use crate::calibration::{Metric, Reference};
+use crate::energy::EnergyBalance;
+use crate::goal::Progress;
+use crate::migration::{Migration, MIGRATIONS};
+use crate::storage::{
+    measurement_params, stored_measurement, stored_muscle_kg, stored_sample, MeasurementRow,
+    Quarantined, Storage, StoredComposition, StoredMeasurement, StoredReading, MEASUREMENT_COLUMNS,
+    SAMPLE_COLUMNS, STORED_COLUMNS,
+};
+use crate::trend::{Sample, Trend};
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use deadpool_postgres::{
+    Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime,
+};
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use simplelog::*;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use strum::EnumString;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::NoTls;
 
+/// Time allowed to establish a connection when the config doesn't set one,
+/// the measurements are spooled while the database is unreachable
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// PostgreSQL storage of the measurements (`mifit` table)
+///
+/// Keeps a small pool of connections, which are verified before reuse
+/// and re-established when lost.
 #[derive(Clone)]
 pub struct Database {
     pub name: String,
-    pub host: String,
-    pub dbname: String,
-    pub username: String,
-    pub password: String,
+    /// apply the pending schema migrations at startup
+    pub auto_migrate: bool,
+    pool: Pool,
+}
+
+/// TLS mode of the connection, with the meaning of the libpq `sslmode`
+#[derive(Clone, Copy, Debug, PartialEq, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum TlsMode {
+    /// plain connection
+    Disable,
+    /// TLS if the server supports it, without a certificate check
+    Prefer,
+    /// TLS, the certificate is only checked against `root_cert` when configured
+    Require,
+    /// TLS with the certificate signed by a trusted CA
+    VerifyCa,
+    /// TLS with the certificate signed by a trusted CA and matching the host name
+    VerifyFull,
+}
+
+/// TLS settings of the connection
+pub struct Tls {
+    pub mode: TlsMode,
+    /// CA certificate (PEM) trusted in addition to the system ones
+    pub root_cert: Option<PathBuf>,
+    /// client certificate and its PKCS #8 key (PEM)
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl Default for Tls {
+    fn default() -> Tls {
+        Tls {
+            mode: TlsMode::Prefer,
+            root_cert: None,
+            client_cert: None,
+        }
+    }
+}
+
+impl Tls {
+    fn connector(&self) -> Result<MakeTlsConnector, Box<dyn std::error::Error>> {
+        let read = |path: &PathBuf| {
+            fs::read(path).map_err(|e| format!("cannot read `{}`: {}", path.display(), e))
+        };
+        let mut builder = TlsConnector::builder();
+        if let Some(path) = &self.root_cert {
+            builder.add_root_certificate(Certificate::from_pem(&read(path)?)?);
+        }
+        if let Some((cert, key)) = &self.client_cert {
+            builder.identity(Identity::from_pkcs8(&read(cert)?, &read(key)?)?);
+        }
+        let verify_ca = match self.mode {
+            TlsMode::Disable | TlsMode::Prefer => false,
+            TlsMode::Require => self.root_cert.is_some(),
+            TlsMode::VerifyCa | TlsMode::VerifyFull => true,
+        };
+        builder
+            .danger_accept_invalid_certs(!verify_ca)
+            .danger_accept_invalid_hostnames(self.mode != TlsMode::VerifyFull);
+
+        Ok(MakeTlsConnector::new(builder.build()?))
+    }
 }
 
 impl Database {
-    pub fn insert_data(&mut self, m: Measurement, p: &Person) -> bool {
-        let connectionstring = format!(
-            "postgres://{}:{}@{}/{}",
-            self.username, self.password, self.host, self.dbname
-        );
-        let client = Client::connect(&connectionstring, NoTls);
-        match client {
-            Ok(mut client) => {
-                 if let Err(e) = client.execute(
-                     "INSERT INTO mifit (time, weight, height, bmi, fat_rate, body_water_rate, bone_mass, metabolism, muscle_rate, visceral_fat)
-                                 VALUES ($1::timestamp AT time zone 'UTC', $2, $3, $4, $5, $6, $7, $8, $9, $10)",
-                     &[&m.date_time, &(m.weight as f64), &(p.height as f64), &(m.bmi as f64), &(m.bf as f64), &(m.water_rate as f64), &(m.bone_mass as f64), &(m.bmr as f64), &(m.muscle_rate as f64), &(m.visceral_fat as f64)],
-                 ) {
-                     error!("{}: error inserting: {:?}", self.name, e);
-                     false
-                 } else {
-                     true
-                 }
-            },
-            Err(e) => {
-                error!("{}: error connecting: {:?}", self.name, e);
-                false
+    /// Create the pool of up to `pool_size` connections (connected on demand)
+    pub fn new(
+        name: &str,
+        mut config: tokio_postgres::Config,
+        tls: &Tls,
+        pool_size: usize,
+    ) -> Result<Database, Box<dyn std::error::Error>> {
+        let connect_timeout = *config
+            .get_connect_timeout()
+            .unwrap_or(&DEFAULT_CONNECT_TIMEOUT);
+        let manager_config = ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        };
+        let manager = match tls.mode {
+            TlsMode::Disable => {
+                config.ssl_mode(SslMode::Disable);
+                Manager::from_config(config, NoTls, manager_config)
+            }
+            mode => {
+                config.ssl_mode(match mode {
+                    TlsMode::Prefer => SslMode::Prefer,
+                    _ => SslMode::Require,
+                });
+                Manager::from_config(config, tls.connector()?, manager_config)
             }
+        };
+        let pool = Pool::builder(manager)
+            .max_size(pool_size)
+            .runtime(Runtime::Tokio1)
+            .create_timeout(Some(connect_timeout))
+            .recycle_timeout(Some(Duration::from_secs(5)))
+            .build()?;
+
+        Ok(Database {
+            name: name.to_string(),
+            auto_migrate: false,
+            pool,
+        })
+    }
+
+    /// Connection from the pool, a single attempt
+    async fn connect(&self) -> Result<Object, Box<dyn std::error::Error>> {
+        Ok(self.pool.get().await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for Database {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn auto_migrate(&self) -> bool {
+        self.auto_migrate
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    async fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.pool.get().await?;
+        client.query_one("SELECT 1", &[]).await?;
+        Ok(())
+    }
+
+    fn is_unreachable(&self, e: &(dyn std::error::Error + 'static)) -> bool {
+        match e.downcast_ref::<PoolError>() {
+            Some(PoolError::Timeout(_)) => true,
+            Some(PoolError::Backend(e)) => match e.code() {
+                // rejected by the server: the credentials or the database name
+                Some(code) => !matches!(&code.code()[..2], "28" | "3D"),
+                // the certificate or the TLS setup
+                None => !e
+                    .source()
+                    .is_some_and(|source| source.is::<native_tls::Error>()),
+            },
+            _ => false,
+        }
+    }
+
+    async fn schema_version(&self) -> Result<i32, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])
+            .await?;
+        if !row.get::<_, bool>(0) {
+            return Ok(0);
+        }
+        let row = client
+            .query_one("SELECT coalesce(max(version), 0) FROM schema_version", &[])
+            .await?;
+
+        Ok(row.get(0))
+    }
+
+    async fn migrate(&self) -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+        let mut client = self.connect().await?;
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (
+                     version int PRIMARY KEY,
+                     description text NOT NULL,
+                     applied timestamptz NOT NULL DEFAULT now()
+                 )",
+            )
+            .await?;
+
+        let transaction = client.transaction().await?;
+        // concurrent migrations wait for each other
+        transaction
+            .batch_execute("LOCK TABLE schema_version IN EXCLUSIVE MODE")
+            .await?;
+        let current: i32 = transaction
+            .query_one("SELECT coalesce(max(version), 0) FROM schema_version", &[])
+            .await?
+            .get(0);
+
+        let mut applied = vec![];
+        for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+            info!(
+                "{}: applying migration {}: {}",
+                self.name, m.version, m.description
+            );
+            transaction
+                .batch_execute(m.sql)
+                .await
+                .map_err(|e| format!("migration {} has failed: {}", m.version, e))?;
+            transaction
+                .execute(
+                    "INSERT INTO schema_version (version, description) VALUES ($1, $2)",
+                    &[&m.version, &m.description],
+                )
+                .await?;
+            applied.push(m.version);
+        }
+        transaction.commit().await?;
+
+        Ok(applied)
+    }
+
+    async fn insert_measurement(
+        &self,
+        m: &Measurement,
+        p: &Person,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let (table, columns, values) = match reason {
+            Some(_) => (
+                "mifit_quarantine",
+                format!("{}, reason", MEASUREMENT_COLUMNS),
+                ", $30)",
+            ),
+            None => (
+                "mifit",
+                MEASUREMENT_COLUMNS.to_string(),
+                ") ON CONFLICT (profile, time) DO NOTHING",
+            ),
+        };
+        let row = MeasurementRow::new(m, p);
+        let mut params = measurement_params!(&row, (dyn ToSql + Sync));
+        if let Some(reason) = &reason {
+            params.push(reason);
+        }
+
+        client
+            .execute(
+                &format!(
+                    "INSERT INTO {} ({})
+                         VALUES ($1::timestamp AT time zone 'UTC', $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29{}",
+                    table, columns, values
+                ),
+                &params,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn fetch_quarantine(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Quarantined>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let rows = client.query(
+            "SELECT id, time AT time zone 'UTC', weight, impedance, reason FROM mifit_quarantine
+                 WHERE profile = $1 ORDER BY time",
+            &[&user],
+        ).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| Quarantined {
+                id: row.get(0),
+                date_time: row.get(1),
+                weight: row.get::<_, f64>(2) as f32,
+                impedance: row.get::<_, Option<f64>>(3).unwrap_or_default() as f32,
+                reason: row.get(4),
+            })
+            .collect())
+    }
+
+    async fn accept_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut client = self.connect().await?;
+        let transaction = client.transaction().await?;
+        let moved = transaction
+            .execute(
+                &format!(
+                    "INSERT INTO mifit ({0}) SELECT {0} FROM mifit_quarantine WHERE id = $1",
+                    MEASUREMENT_COLUMNS
+                ),
+                &[&id],
+            )
+            .await?;
+        transaction
+            .execute("DELETE FROM mifit_quarantine WHERE id = $1", &[&id])
+            .await?;
+        transaction.commit().await?;
+
+        Ok(moved)
+    }
+
+    async fn delete_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        Ok(client
+            .execute("DELETE FROM mifit_quarantine WHERE id = $1", &[&id])
+            .await?)
+    }
+
+    async fn fetch_readings(
+        &self,
+        user: &str,
+        since: Option<NaiveDate>,
+    ) -> Result<Vec<StoredReading>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let since = since.and_then(|d| d.and_hms_opt(0, 0, 0));
+        let rows = client
+            .query(
+                "SELECT time AT time zone 'UTC', weight, impedance, flags FROM mifit
+                 WHERE profile = $1 AND impedance IS NOT NULL
+                   AND ($2::timestamp IS NULL OR time >= $2::timestamp AT time zone 'UTC')
+                 ORDER BY time",
+                &[&user, &since],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| StoredReading {
+                date_time: row.get(0),
+                weight: row.get::<_, f64>(1) as f32,
+                impedance: row.get::<_, f64>(2) as f32,
+                flags: row.get::<_, Option<i32>>(3).unwrap_or_default() as u16,
+            })
+            .collect())
+    }
+
+    async fn fetch_measurements(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredMeasurement>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT time AT time zone 'UTC', weight, flags, algorithm, {} FROM mifit
+                         WHERE profile = $1
+                         ORDER BY time",
+                    STORED_COLUMNS
+                ),
+                &[&user],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                stored_measurement(
+                    row.get(0),
+                    row.get(1),
+                    row.get(2),
+                    row.get(3),
+                    std::array::from_fn(|i| row.get(i + 4)),
+                )
+            })
+            .collect())
+    }
+
+    async fn fetch_compositions(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredComposition>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                "SELECT time AT time zone 'UTC', weight, body_water_rate, metabolism, visceral_fat, fat_rate, muscle_kg, muscle_rate, bone_mass FROM mifit
+                 WHERE profile = $1 AND impedance IS NULL
+                 ORDER BY time",
+                &[&user],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let value = |i: usize| row.get::<_, Option<f64>>(i).map(|v| v as f32);
+                let weight = row.get::<_, f64>(1) as f32;
+                StoredComposition {
+                    date_time: row.get(0),
+                    weight,
+                    composition: Composition {
+                        water_rate: value(2),
+                        bmr: value(3),
+                        visceral_fat: value(4),
+                        bf: value(5),
+                        muscle_kg: stored_muscle_kg(row.get(6), row.get(7), weight),
+                        bone_mass: value(8),
+                    },
+                }
+            })
+            .collect())
+    }
+
+    async fn update_derived(
+        &self,
+        measurements: &[Measurement],
+        p: &Person,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut client = self.connect().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare(
+            "UPDATE mifit SET height = $3, bmi = $4, fat_rate = $5, body_water_rate = $6, bone_mass = $7, metabolism = $8, muscle_rate = $9, visceral_fat = $10, algorithm = $11, muscle_kg = $12, sex = $13, age = $14,
+                     fat_rate_calibrated = $15, muscle_kg_calibrated = $16, body_water_rate_calibrated = $17, bone_mass_calibrated = $18, visceral_fat_calibrated = $19, skeletal_muscle_kg = $20
+                 WHERE time = $1::timestamp AT time zone 'UTC' AND profile = $2",
+        ).await?;
+        let mut updated = 0;
+        for m in measurements {
+            let c = m.calibrated;
+            let calibrated = [c.bf, c.muscle_kg, c.water_rate, c.bone_mass, c.visceral_fat]
+                .map(|v| v.map(|v| v as f64));
+            updated += transaction
+                .execute(
+                    &statement,
+                    &[
+                        &m.date_time,
+                        &p.name,
+                        &(p.height as f64),
+                        &(m.bmi as f64),
+                        &m.bf.map(|v| v as f64),
+                        &m.water_rate.map(|v| v as f64),
+                        &m.bone_mass.map(|v| v as f64),
+                        &(m.bmr as f64),
+                        &m.muscle_rate.map(|v| v as f64),
+                        &m.visceral_fat.map(|v| v as f64),
+                        &m.sources.to_string(),
+                        &m.muscle_kg.map(|v| v as f64),
+                        &(p.sex as i16),
+                        &(m.age as f64),
+                        &calibrated[0],
+                        &calibrated[1],
+                        &calibrated[2],
+                        &calibrated[3],
+                        &calibrated[4],
+                        &m.skeletal_muscle_kg.map(|v| v as f64),
+                    ],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(updated)
+    }
+
+    async fn fetch_samples(
+        &self,
+        user: &str,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT time AT time zone 'UTC', {} FROM mifit
+                         WHERE profile = $1
+                           AND ($2::timestamp IS NULL OR time >= $2::timestamp AT time zone 'UTC')
+                         ORDER BY time",
+                    SAMPLE_COLUMNS
+                ),
+                &[&user, &since],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                stored_sample(
+                    row.get(0),
+                    row.get(1),
+                    std::array::from_fn(|i| row.get(i + 2)),
+                )
+            })
+            .collect())
+    }
+
+    async fn update_trends(
+        &self,
+        user: &str,
+        samples: &[Sample],
+        trends: &[Trend],
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut client = self.connect().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare(
+            "UPDATE mifit SET weight_trend = $3, weight_avg7 = $4, weight_avg30 = $5, weight_week_change = $6, fat_week_change = $7, muscle_week_change = $8
+                 WHERE time = $1::timestamp AT time zone 'UTC' AND profile = $2",
+        ).await?;
+        let mut updated = 0;
+        for (s, t) in samples.iter().zip(trends) {
+            updated += transaction
+                .execute(
+                    &statement,
+                    &[
+                        &s.date_time,
+                        &user,
+                        &(t.weight as f64),
+                        &(t.weight_avg_7d as f64),
+                        &(t.weight_avg_30d as f64),
+                        &t.weight_rate.map(|v| v as f64),
+                        &t.fat_rate.map(|v| v as f64),
+                        &t.muscle_rate.map(|v| v as f64),
+                    ],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(updated)
+    }
+
+    async fn insert_progress(
+        &self,
+        user: &str,
+        progress: &[Progress],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        for pr in progress {
+            client.execute(
+                "INSERT INTO goal_progress (time, profile, metric, target, current, remaining, weekly_rate, projected)
+                     VALUES ($1::timestamp AT time zone 'UTC', $2, $3, $4, $5, $6, $7, $8)
+                     ON CONFLICT (profile, time, metric) DO NOTHING",
+                &[
+                    &pr.date_time,
+                    &user,
+                    &pr.goal.metric.to_string(),
+                    &(pr.goal.target as f64),
+                    &(pr.current as f64),
+                    &(pr.remaining as f64),
+                    &pr.weekly_rate.map(|v| v as f64),
+                    &pr.projected,
+                ],
+            ).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn upsert_energy_weekly(
+        &self,
+        user: &str,
+        weeks: &[(NaiveDate, EnergyBalance)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        for (week, e) in weeks {
+            client.execute(
+                "INSERT INTO energy_weekly (profile, week, bmr, tdee, fat_mass_change, lean_mass_change, balance, intake)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                     ON CONFLICT (profile, week) DO UPDATE SET bmr = $3, tdee = $4, fat_mass_change = $5, lean_mass_change = $6, balance = $7, intake = $8",
+                &[
+                    &user,
+                    week,
+                    &(e.bmr as f64),
+                    &(e.tdee as f64),
+                    &(e.fat_mass_rate as f64),
+                    &(e.lean_mass_rate as f64),
+                    &(e.balance as f64),
+                    &(e.intake as f64),
+                ],
+            ).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_reference(
+        &self,
+        user: &str,
+        date: NaiveDate,
+        metric: Metric,
+        value: f32,
+        source: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        client.execute(
+            "INSERT INTO reference_measurement (profile, date, metric, value, source) VALUES ($1, $2, $3, $4, $5)",
+            &[&user, &date, &metric.to_string(), &(value as f64), &source],
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn fetch_references(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Reference>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let rows = client.query(
+            "SELECT id, date, metric, value, source FROM reference_measurement WHERE profile = $1 ORDER BY date",
+            &[&user],
+        ).await?;
+
+        let mut references = vec![];
+        for row in rows {
+            references.push(Reference {
+                id: row.get(0),
+                date: row.get(1),
+                metric: row.get::<_, String>(2).parse()?,
+                value: row.get::<_, f64>(3) as f32,
+                source: row.get(4),
+            });
         }
+
+        Ok(references)
+    }
+
+    async fn delete_reference(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        Ok(client
+            .execute("DELETE FROM reference_measurement WHERE id = $1", &[&id])
+            .await?)
+    }
+
+    async fn fetch_scale_average(
+        &self,
+        user: &str,
+        metric: Metric,
+        date: NaiveDate,
+    ) -> Result<Option<f32>, Box<dyn std::error::Error>> {
+        let client = self.connect().await?;
+        let row = client
+            .query_one(
+                &format!(
+                    "SELECT avg({})::float8 FROM mifit
+                         WHERE profile = $1 AND abs(extract(epoch FROM (time AT time zone 'UTC') - $2::date::timestamp)) <= 3 * 86400",
+                    metric.column()
+                ),
+                &[&user, &date],
+            )
+            .await?;
+
+        Ok(row.get::<_, Option<f64>>(0).map(|v| v as f32))
     }
 }
@@ -0,0 +1,244 @@
+use crate::trend::{weekly_rate, Sample};
+use chrono::{Datelike, Duration, NaiveDate};
+use std::fmt;
+use strum::{Display, EnumString};
+
+/// Energy stored in a kg of body fat (kcal), Hall (2008)
+pub const FAT_KCAL_PER_KG: f32 = 9440.0;
+/// Energy stored in a kg of lean mass (kcal), Hall (2008)
+pub const LEAN_KCAL_PER_KG: f32 = 1816.0;
+
+/// Physical activity level, multiplier of the basal metabolism
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ActivityLevel {
+    /// little or no exercise
+    Sedentary,
+    /// exercise 1-3 days a week
+    Light,
+    /// exercise 3-5 days a week
+    Moderate,
+    /// exercise 6-7 days a week
+    Active,
+    /// hard exercise or physical job
+    VeryActive,
+}
+
+impl ActivityLevel {
+    pub fn factor(&self) -> f32 {
+        match self {
+            ActivityLevel::Sedentary => 1.2,
+            ActivityLevel::Light => 1.375,
+            ActivityLevel::Moderate => 1.55,
+            ActivityLevel::Active => 1.725,
+            ActivityLevel::VeryActive => 1.9,
+        }
+    }
+}
+
+/// Estimated energy expenditure and balance over a period
+pub struct EnergyBalance {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// average basal metabolism (kcal/day)
+    pub bmr: f32,
+    /// total daily energy expenditure (kcal/day)
+    pub tdee: f32,
+    /// fat mass change (kg/week)
+    pub fat_mass_rate: f32,
+    /// lean mass change (kg/week)
+    pub lean_mass_rate: f32,
+    /// average daily surplus (positive) or deficit (negative) (kcal/day)
+    pub balance: f32,
+    /// implied average energy intake (kcal/day)
+    pub intake: f32,
+}
+
+impl fmt::Display for EnergyBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} - {}: BMR {:.0} kcal, TDEE {:.0} kcal, fat {:+.2} kg/week, lean {:+.2} kg/week, balance {:+.0} kcal/day, intake {:.0} kcal/day",
+            self.from,
+            self.to,
+            self.bmr,
+            self.tdee,
+            self.fat_mass_rate,
+            self.lean_mass_rate,
+            self.balance,
+            self.intake
+        )
+    }
+}
+
+fn fat_mass(s: &Sample) -> Option<f32> {
+    s.fat.map(|fat| s.weight * fat / 100.0)
+}
+
+impl EnergyBalance {
+    /// Estimate from the samples of the period using the `activity` factor.
+    ///
+    /// Needs at least two measurements (with the body fat) at different times,
+    /// and the basal metabolism of at least one.
+    pub fn estimate(samples: &[Sample], activity: f32) -> Option<EnergyBalance> {
+        let window: Vec<&Sample> = samples.iter().collect();
+        let fat_mass_rate = weekly_rate(&window, fat_mass)?;
+        let lean_mass_rate = weekly_rate(&window, |s| fat_mass(s).map(|fm| s.weight - fm))?;
+
+        let bmrs: Vec<f32> = samples.iter().filter_map(|s| s.bmr).collect();
+        if bmrs.is_empty() {
+            return None;
+        }
+        let bmr = bmrs.iter().sum::<f32>() / bmrs.len() as f32;
+        let tdee = bmr * activity;
+        let balance = (fat_mass_rate * FAT_KCAL_PER_KG + lean_mass_rate * LEAN_KCAL_PER_KG) / 7.0;
+
+        Some(EnergyBalance {
+            from: samples.first()?.date_time.date(),
+            to: samples.last()?.date_time.date(),
+            bmr,
+            tdee,
+            fat_mass_rate,
+            lean_mass_rate,
+            balance,
+            intake: tdee + balance,
+        })
+    }
+
+    /// Estimates per calendar week (starting on Monday) of the samples (ordered by time)
+    pub fn weekly(samples: &[Sample], activity: f32) -> Vec<(NaiveDate, EnergyBalance)> {
+        let mut weeks = vec![];
+        let mut start = 0;
+        while start < samples.len() {
+            let week = week_start(samples[start].date_time.date());
+            let end = samples[start..]
+                .iter()
+                .position(|s| week_start(s.date_time.date()) != week)
+                .map(|i| start + i)
+                .unwrap_or(samples.len());
+            if let Some(e) = EnergyBalance::estimate(&samples[start..end], activity) {
+                weeks.push((week, e));
+            }
+            start = end;
+        }
+        weeks
+    }
+}
+
+/// Monday of the week of the date
+pub fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-01-02 is a Monday
+    fn sample(day: u32, weight: f32, fat_mass: f32, bmr: f32) -> Sample {
+        Sample {
+            date_time: NaiveDate::from_ymd_opt(2023, 1, day)
+                .unwrap()
+                .and_hms_opt(7, 0, 0)
+                .unwrap(),
+            weight,
+            fat: Some(fat_mass / weight * 100.0),
+            muscle: None,
+            impedance: None,
+            bmr: Some(bmr),
+            trend_weight: None,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn stable_weight_is_maintenance() {
+        let samples = [sample(2, 80.0, 20.0, 1700.0), sample(5, 80.0, 20.0, 1800.0)];
+        let e = EnergyBalance::estimate(&samples, ActivityLevel::Moderate.factor()).unwrap();
+        assert_eq!(e.bmr, 1750.0);
+        assert!(close(e.tdee, 1750.0 * 1.55));
+        assert!(close(e.balance, 0.0));
+        assert!(close(e.intake, e.tdee));
+    }
+
+    #[test]
+    fn fat_loss_deficit() {
+        // 0.5 kg of fat lost in a week, the lean mass unchanged
+        let samples = [sample(2, 80.0, 20.0, 1700.0), sample(9, 79.5, 19.5, 1700.0)];
+        let e = EnergyBalance::estimate(&samples, 1.2).unwrap();
+        assert!(close(e.fat_mass_rate, -0.5));
+        assert!(close(e.lean_mass_rate, 0.0));
+        assert!(close(e.balance, -0.5 * FAT_KCAL_PER_KG / 7.0));
+        assert!(close(e.intake, 1700.0 * 1.2 - 674.29));
+    }
+
+    #[test]
+    fn lean_gain_surplus() {
+        // 1 kg of lean mass gained in a week, the fat mass unchanged
+        let samples = [sample(2, 80.0, 20.0, 1700.0), sample(9, 81.0, 20.0, 1700.0)];
+        let e = EnergyBalance::estimate(&samples, 1.2).unwrap();
+        assert!(close(e.balance, LEAN_KCAL_PER_KG / 7.0));
+    }
+
+    #[test]
+    fn needs_two_measurements_with_body_fat() {
+        assert!(EnergyBalance::estimate(&[sample(2, 80.0, 20.0, 1700.0)], 1.2).is_none());
+        let mut child = sample(3, 40.0, 8.0, 1200.0);
+        child.fat = None;
+        let samples = [sample(2, 80.0, 20.0, 1700.0), child];
+        assert!(EnergyBalance::estimate(&samples, 1.2).is_none());
+    }
+
+    #[test]
+    fn metabolism_averaged_where_stored() {
+        let mut imported = sample(5, 80.0, 20.0, 0.0);
+        imported.bmr = None;
+        let samples = [sample(2, 80.0, 20.0, 1700.0), imported];
+        let e = EnergyBalance::estimate(&samples, 1.2).unwrap();
+        assert_eq!(e.bmr, 1700.0);
+
+        let mut first = sample(2, 80.0, 20.0, 0.0);
+        first.bmr = None;
+        let samples = [first, samples[1].clone()];
+        assert!(EnergyBalance::estimate(&samples, 1.2).is_none());
+    }
+
+    #[test]
+    fn calendar_weeks() {
+        let samples = [
+            sample(2, 80.0, 20.0, 1700.0),
+            sample(8, 79.8, 19.8, 1700.0),
+            sample(9, 79.6, 19.6, 1700.0),
+            sample(12, 79.4, 19.4, 1700.0),
+            sample(16, 79.2, 19.2, 1700.0),
+        ];
+        let weeks = EnergyBalance::weekly(&samples, 1.2);
+        // the last week has a single measurement only
+        let starts: Vec<NaiveDate> = weeks.iter().map(|(week, _)| *week).collect();
+        assert_eq!(
+            starts,
+            [
+                NaiveDate::from_ymd_opt(2023, 1, 2).unwrap(),
+                NaiveDate::from_ymd_opt(2023, 1, 9).unwrap()
+            ]
+        );
+        assert_eq!(
+            weeks[1].1.from,
+            NaiveDate::from_ymd_opt(2023, 1, 9).unwrap()
+        );
+        assert_eq!(weeks[1].1.to, NaiveDate::from_ymd_opt(2023, 1, 12).unwrap());
+        assert_eq!(
+            week_start(NaiveDate::from_ymd_opt(2023, 1, 8).unwrap()),
+            NaiveDate::from_ymd_opt(2023, 1, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn activity_levels() {
+        assert_eq!("very_active".parse(), Ok(ActivityLevel::VeryActive));
+        assert_eq!(ActivityLevel::Sedentary.factor(), 1.2);
+    }
+}
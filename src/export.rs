@@ -0,0 +1,274 @@
+use crate::body::{Measurement, Person, Sources};
+use crate::rating::{rate_bmi, rate_body_fat, ADULT_AGE};
+use crate::storage::{Storage, StoredMeasurement};
+use crate::trend::Trend;
+use chrono::NaiveDate;
+use std::io::Write;
+use strum::{Display, EnumString};
+
+/// Format of the exported measurements
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Openscale,
+    Fit,
+    Fhir,
+}
+
+/// Time format of the exported measurements
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Columns of the exported CSV, named as the database columns where there is one
+pub const CSV_COLUMNS: &[&str] = &[
+    "time",
+    "profile",
+    "weight",
+    "impedance",
+    "flags",
+    "age",
+    "bmi",
+    "bmi_rating",
+    "bmi_for_age_z_score",
+    "bmi_for_age_percentile",
+    "bmi_for_age_rating",
+    "body_water_rate",
+    "metabolism",
+    "visceral_fat",
+    "fat_rate",
+    "fat_rating",
+    "muscle_kg",
+    "muscle_rate",
+    "skeletal_muscle_kg",
+    "bone_mass",
+    "body_water_rate_source",
+    "metabolism_source",
+    "visceral_fat_source",
+    "fat_rate_source",
+    "muscle_source",
+    "skeletal_muscle_source",
+    "bone_mass_source",
+    "warnings",
+    "weight_trend",
+    "weight_avg7",
+    "weight_avg30",
+    "weight_week_change",
+    "fat_week_change",
+    "muscle_week_change",
+    "fat_rate_calibrated",
+    "muscle_kg_calibrated",
+    "body_water_rate_calibrated",
+    "bone_mass_calibrated",
+    "visceral_fat_calibrated",
+    "weight_uncertainty",
+    "bmi_uncertainty",
+    "body_water_rate_uncertainty",
+    "metabolism_uncertainty",
+    "visceral_fat_uncertainty",
+    "fat_rate_uncertainty",
+    "muscle_kg_uncertainty",
+    "muscle_rate_uncertainty",
+    "bone_mass_uncertainty",
+];
+
+/// Stored measurements of the profile between the dates (inclusive) with their values as stored,
+/// or with `recompute` computed from the raw inputs with the current profile and calibration,
+/// with the trends over the whole history.
+/// The values of the measurements without the impedance are always taken as stored.
+pub async fn fetch_history(
+    db: &dyn Storage,
+    p: &Person,
+    (since, until): (Option<NaiveDate>, Option<NaiveDate>),
+    recompute: bool,
+) -> Result<Vec<Measurement>, Box<dyn std::error::Error>> {
+    let in_range = |m: &Measurement| {
+        let date = m.date_time.date();
+        since.is_none_or(|since| date >= since) && until.is_none_or(|until| date <= until)
+    };
+    if !recompute {
+        let mut measurements: Vec<Measurement> = db
+            .fetch_measurements(&p.name)
+            .await?
+            .iter()
+            .map(|s| stored(p, s))
+            .collect();
+        measurements.retain(in_range);
+        return Ok(measurements);
+    }
+
+    let calibration = db.fetch_calibration(&p.name).await?;
+    let mut measurements: Vec<Measurement> = db
+        .fetch_readings(&p.name, None)
+        .await?
+        .iter()
+        .map(|r| {
+            let mut m = p.measure(r.date_time, r.weight, r.impedance, r.flags);
+            m.calibrated = calibration.apply(&m);
+            m
+        })
+        .collect();
+    // measurements without the impedance (imported) keep the stored values
+    for c in db.fetch_compositions(&p.name).await? {
+        measurements.push(p.measure_known(c.date_time, c.weight, &c.composition, "imported"));
+    }
+    measurements.sort_by_key(|m| m.date_time);
+
+    let samples: Vec<_> = measurements.iter().map(|m| m.sample()).collect();
+    for (m, trend) in measurements.iter_mut().zip(Trend::series(&samples)) {
+        m.trend = Some(trend);
+    }
+    measurements.retain(in_range);
+
+    Ok(measurements)
+}
+
+/// Measurement with the stored values, the missing ones (eg. the age of the older rows)
+/// are computed with the current profile. The uncertainty is not stored.
+fn stored(p: &Person, s: &StoredMeasurement) -> Measurement {
+    let source = if s.impedance.is_some() {
+        "stored"
+    } else {
+        "imported"
+    };
+    let mut m = p.measure_known(s.date_time, s.weight, &s.composition, source);
+    m.impedance = s.impedance.unwrap_or_default();
+    m.flags = s.flags;
+    m.age = s.age.unwrap_or(m.age);
+    m.bmi = s.bmi.unwrap_or(m.bmi);
+    m.muscle_rate = s.muscle_rate.or(m.muscle_rate);
+    m.skeletal_muscle_kg = s.skeletal_muscle_kg;
+    if let Some(algorithm) = &s.algorithm {
+        m.sources = Sources::parse(algorithm);
+    }
+    if m.skeletal_muscle_kg.is_none() {
+        m.sources.skeletal_muscle = "-";
+    }
+    let adult = m.age >= ADULT_AGE;
+    m.bmi_rating = adult.then(|| rate_bmi(m.bmi, m.age));
+    m.fat_rating =
+        m.bf.filter(|_| adult)
+            .map(|bf| rate_body_fat(bf, p.sex, m.age));
+    m.trend = s.trend;
+    m.calibrated = s.calibrated;
+    m
+}
+
+/// Write the measurements as CSV with all the values
+pub fn write_csv<W: Write>(
+    writer: W,
+    p: &Person,
+    measurements: &[Measurement],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(CSV_COLUMNS)?;
+
+    let num = |v: f32| v.to_string();
+    let opt = |v: Option<f32>| v.map(num).unwrap_or_default();
+    for m in measurements {
+        let s = &m.sources;
+        let t = m.trend.as_ref();
+        let c = &m.calibrated;
+        let u = m.uncertainty.as_ref();
+        writer.write_record(&[
+            m.date_time.format(TIME_FORMAT).to_string(),
+            p.name.clone(),
+            num(m.weight),
+            if m.impedance > 0.0 {
+                num(m.impedance)
+            } else {
+                String::new()
+            },
+            m.flags.to_string(),
+            num(m.age),
+            num(m.bmi),
+            m.bmi_rating.map(|r| r.to_string()).unwrap_or_default(),
+            opt(m.bmi_for_age.map(|b| b.z_score)),
+            opt(m.bmi_for_age.map(|b| b.percentile)),
+            m.bmi_for_age
+                .map(|b| b.rating.to_string())
+                .unwrap_or_default(),
+            opt(m.water_rate),
+            num(m.bmr),
+            opt(m.visceral_fat),
+            opt(m.bf),
+            m.fat_rating.map(|r| r.to_string()).unwrap_or_default(),
+            opt(m.muscle_kg),
+            opt(m.muscle_rate),
+            opt(m.skeletal_muscle_kg),
+            opt(m.bone_mass),
+            s.water_rate.to_string(),
+            s.bmr.to_string(),
+            s.visceral_fat.to_string(),
+            s.bf.to_string(),
+            s.muscle.to_string(),
+            s.skeletal_muscle.to_string(),
+            s.bone_mass.to_string(),
+            m.warnings.join("; "),
+            opt(t.map(|t| t.weight)),
+            opt(t.map(|t| t.weight_avg_7d)),
+            opt(t.map(|t| t.weight_avg_30d)),
+            opt(t.and_then(|t| t.weight_rate)),
+            opt(t.and_then(|t| t.fat_rate)),
+            opt(t.and_then(|t| t.muscle_rate)),
+            opt(c.bf),
+            opt(c.muscle_kg),
+            opt(c.water_rate),
+            opt(c.bone_mass),
+            opt(c.visceral_fat),
+            opt(u.map(|u| u.weight)),
+            opt(u.map(|u| u.bmi)),
+            opt(u.map(|u| u.water_rate)),
+            opt(u.map(|u| u.bmr)),
+            opt(u.map(|u| u.visceral_fat)),
+            opt(u.map(|u| u.bf)),
+            opt(u.map(|u| u.muscle_kg)),
+            opt(u.map(|u| u.muscle_rate)),
+            opt(u.map(|u| u.bone_mass)),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite::Sqlite;
+    use futures::executor::block_on;
+    use std::path::Path;
+
+    #[test]
+    fn stored_values_unless_recomputed() {
+        let db = Sqlite::open("test", Path::new(":memory:")).unwrap();
+        block_on(db.migrate()).unwrap();
+        let mut p = crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap());
+        p.algorithms = vec![&crate::algorithm::Sun, &crate::algorithm::Janssen];
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let m = p.measure(date.and_hms_opt(8, 0, 0).unwrap(), 80.0, 500.0, 0x02);
+        block_on(db.insert_measurement(&m, &p, None)).unwrap();
+
+        // the profile changed since the measurement was stored
+        p.algorithms = vec![];
+        p.height = 170.0;
+        let stored = block_on(fetch_history(&db, &p, (None, None), false)).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].bf, m.bf);
+        assert_eq!(stored[0].bmi, m.bmi);
+        assert_eq!(stored[0].skeletal_muscle_kg, m.skeletal_muscle_kg);
+        assert_eq!(stored[0].sources.to_string(), m.sources.to_string());
+        assert_eq!(stored[0].impedance, 500.0);
+        assert_eq!(stored[0].flags, 0x02);
+        assert!(stored[0].uncertainty.is_none());
+
+        let recomputed = block_on(fetch_history(&db, &p, (None, None), true)).unwrap();
+        assert_ne!(recomputed[0].bf, m.bf);
+        assert_eq!(recomputed[0].sources.bf, "mifit");
+        assert!(recomputed[0].uncertainty.is_some());
+
+        let until = date.pred_opt();
+        assert!(block_on(fetch_history(&db, &p, (None, until), false))
+            .unwrap()
+            .is_empty());
+    }
+}
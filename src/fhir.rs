@@ -0,0 +1,299 @@
+use crate::body::{Measurement, Person};
+use serde_json::{json, Value};
+use std::io::Write;
+
+const LOINC: &str = "http://loinc.org";
+const UCUM: &str = "http://unitsofmeasure.org";
+const OBSERVATION_CATEGORY: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+/// Code system of the scale values without a LOINC code in common use
+const BLUESCALE_METRIC: &str = "https://github.com/manio/bluescale/metric";
+/// Identifier system of the profile names
+const BLUESCALE_PROFILE: &str = "https://github.com/manio/bluescale/profile";
+
+/// Code of an observed value
+struct Code {
+    system: &'static str,
+    code: &'static str,
+    display: &'static str,
+    /// UCUM unit
+    unit: &'static str,
+    /// a vital sign of the FHIR vital signs profile
+    vital_sign: bool,
+}
+
+const WEIGHT: Code = Code {
+    system: LOINC,
+    code: "29463-7",
+    display: "Body weight",
+    unit: "kg",
+    vital_sign: true,
+};
+const BMI: Code = Code {
+    system: LOINC,
+    code: "39156-5",
+    display: "Body mass index (BMI) [Ratio]",
+    unit: "kg/m2",
+    vital_sign: true,
+};
+const BODY_FAT: Code = Code {
+    system: LOINC,
+    code: "41982-0",
+    display: "Percentage of body fat Measured",
+    unit: "%",
+    vital_sign: false,
+};
+const BODY_WATER: Code = Code {
+    system: BLUESCALE_METRIC,
+    code: "body_water_rate",
+    display: "Body water percentage",
+    unit: "%",
+    vital_sign: false,
+};
+const MUSCLE_MASS: Code = Code {
+    system: BLUESCALE_METRIC,
+    code: "muscle_kg",
+    display: "Muscle mass",
+    unit: "kg",
+    vital_sign: false,
+};
+const BONE_MASS: Code = Code {
+    system: BLUESCALE_METRIC,
+    code: "bone_mass",
+    display: "Bone mass",
+    unit: "kg",
+    vital_sign: false,
+};
+const VISCERAL_FAT: Code = Code {
+    system: BLUESCALE_METRIC,
+    code: "visceral_fat",
+    display: "Visceral fat rating",
+    unit: "1",
+    vital_sign: false,
+};
+const BMR: Code = Code {
+    system: BLUESCALE_METRIC,
+    code: "metabolism",
+    display: "Basal metabolic rate",
+    unit: "kcal/d",
+    vital_sign: false,
+};
+
+/// Id of the `Patient` of the profile: the name with the characters not allowed in ids replaced
+pub fn patient_id(p: &Person) -> String {
+    p.name
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '.' => c,
+            _ => '-',
+        })
+        .take(64)
+        .collect()
+}
+
+/// `Patient` of the profile, referenced by its `fullUrl` from the observations
+fn patient(p: &Person, full_url: &str) -> Value {
+    json!({
+        "fullUrl": full_url,
+        "resource": {
+            "resourceType": "Patient",
+            "id": patient_id(p),
+            "identifier": [{
+                "system": BLUESCALE_PROFILE,
+                "value": p.name,
+            }],
+            "name": [{
+                "text": p.name,
+            }],
+            "gender": if p.sex == 1 { "male" } else { "female" },
+            "birthDate": p.birthday.format("%Y-%m-%d").to_string(),
+        },
+    })
+}
+
+fn observation(p: &Person, patient: &str, m: &Measurement, code: &Code, value: f32) -> Value {
+    let category = if code.vital_sign {
+        ("vital-signs", "Vital Signs")
+    } else {
+        ("exam", "Exam")
+    };
+    json!({
+        "fullUrl": format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+        "resource": {
+            "resourceType": "Observation",
+            "status": "final",
+            "category": [{
+                "coding": [{
+                    "system": OBSERVATION_CATEGORY,
+                    "code": category.0,
+                    "display": category.1,
+                }],
+            }],
+            "code": {
+                "coding": [{
+                    "system": code.system,
+                    "code": code.code,
+                    "display": code.display,
+                }],
+                "text": code.display,
+            },
+            "subject": {
+                "reference": patient,
+                "display": p.name,
+            },
+            "effectiveDateTime": m.date_time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            "valueQuantity": {
+                "value": (value as f64 * 100.0).round() / 100.0,
+                "unit": code.unit,
+                "system": UCUM,
+                "code": code.unit,
+            },
+        },
+    })
+}
+
+/// FHIR R4 `collection` Bundle with the `Patient` of the profile and an `Observation`
+/// for each value of the measurements (the calibrated values where there are some)
+pub fn bundle(p: &Person, measurements: &[Measurement]) -> Value {
+    let patient_url = format!("urn:uuid:{}", uuid::Uuid::new_v4());
+    let mut entries = vec![patient(p, &patient_url)];
+    for m in measurements {
+        let c = &m.calibrated;
+        let values = [
+            (&WEIGHT, Some(m.weight)),
+            (&BMI, Some(m.bmi)),
+            (&BODY_FAT, c.bf.or(m.bf)),
+            (&BODY_WATER, c.water_rate.or(m.water_rate)),
+            (&MUSCLE_MASS, c.muscle_kg.or(m.muscle_kg)),
+            (&BONE_MASS, c.bone_mass.or(m.bone_mass)),
+            (&VISCERAL_FAT, c.visceral_fat.or(m.visceral_fat)),
+            (&BMR, Some(m.bmr)),
+        ];
+        for (code, value) in values {
+            if let Some(value) = value {
+                entries.push(observation(p, &patient_url, m, code, value));
+            }
+        }
+    }
+
+    json!({
+        "resourceType": "Bundle",
+        "type": "collection",
+        "entry": entries,
+    })
+}
+
+/// Write the measurements as a FHIR Bundle (JSON)
+pub fn write_json<W: Write>(
+    mut writer: W,
+    p: &Person,
+    measurements: &[Measurement],
+) -> Result<(), Box<dyn std::error::Error>> {
+    serde_json::to_writer_pretty(&mut writer, &bundle(p, measurements))?;
+    writeln!(writer)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Composition;
+    use chrono::NaiveDate;
+
+    fn person() -> Person {
+        crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap())
+    }
+
+    fn measurement(p: &Person, composition: &Composition) -> Measurement {
+        let date_time = NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        p.measure_known(date_time, 80.0, composition, "test")
+    }
+
+    /// Code system, code and the UCUM unit of each observation
+    fn codes(bundle: &Value) -> Vec<(String, String, String)> {
+        bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| &e["resource"])
+            .filter(|r| r["resourceType"] == "Observation")
+            .map(|r| {
+                let coding = &r["code"]["coding"][0];
+                let quantity = &r["valueQuantity"];
+                assert_eq!(quantity["system"], UCUM);
+                assert_eq!(quantity["unit"], quantity["code"]);
+                (
+                    coding["system"].as_str().unwrap().to_string(),
+                    coding["code"].as_str().unwrap().to_string(),
+                    quantity["code"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loinc_codes_and_ucum_units() {
+        let p = person();
+        let composition = Composition {
+            bf: Some(20.0),
+            water_rate: Some(55.0),
+            muscle_kg: Some(60.0),
+            bone_mass: Some(3.1),
+            visceral_fat: Some(9.0),
+            bmr: Some(1700.0),
+        };
+        let b = bundle(&p, &[measurement(&p, &composition)]);
+        let code = |system: &str, code: &str, unit: &str| {
+            (system.to_string(), code.to_string(), unit.to_string())
+        };
+        assert_eq!(
+            codes(&b),
+            [
+                code(LOINC, "29463-7", "kg"),
+                code(LOINC, "39156-5", "kg/m2"),
+                code(LOINC, "41982-0", "%"),
+                code(BLUESCALE_METRIC, "body_water_rate", "%"),
+                code(BLUESCALE_METRIC, "muscle_kg", "kg"),
+                code(BLUESCALE_METRIC, "bone_mass", "kg"),
+                code(BLUESCALE_METRIC, "visceral_fat", "1"),
+                code(BLUESCALE_METRIC, "metabolism", "kcal/d"),
+            ]
+        );
+        let weight = &b["entry"][1]["resource"];
+        assert_eq!(weight["valueQuantity"]["value"], 80.0);
+        assert_eq!(weight["effectiveDateTime"], "2026-10-18T08:00:00Z");
+        assert_eq!(weight["category"][0]["coding"][0]["code"], "vital-signs");
+        assert_eq!(
+            b["entry"][3]["resource"]["category"][0]["coding"][0]["code"],
+            "exam"
+        );
+    }
+
+    #[test]
+    fn missing_values_skipped() {
+        let p = person();
+        let b = bundle(&p, &[measurement(&p, &Composition::default())]);
+        let codes: Vec<String> = codes(&b).into_iter().map(|(_, code, _)| code).collect();
+        // the BMR is computed when not known
+        assert_eq!(codes, ["29463-7", "39156-5", "metabolism"]);
+    }
+
+    #[test]
+    fn patient_referenced() {
+        let mut p = person();
+        p.name = "john doe".to_string();
+        let b = bundle(&p, &[measurement(&p, &Composition::default())]);
+        let patient = &b["entry"][0];
+        assert_eq!(patient["resource"]["resourceType"], "Patient");
+        assert_eq!(patient["resource"]["id"], "john-doe");
+        assert_eq!(patient["resource"]["identifier"][0]["value"], "john doe");
+        assert_eq!(patient["resource"]["gender"], "male");
+        assert_eq!(patient["resource"]["birthDate"], "1990-06-15");
+        let subject = &b["entry"][1]["resource"]["subject"];
+        assert_eq!(subject["reference"], patient["fullUrl"]);
+        assert_eq!(subject["display"], "john doe");
+    }
+}
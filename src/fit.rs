@@ -0,0 +1,313 @@
+use crate::body::{safe_name, Measurement, Person};
+use chrono::NaiveDateTime;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Seconds from the Unix epoch to the FIT epoch (1989-12-31 00:00:00 UTC)
+const FIT_EPOCH: i64 = 631_065_600;
+/// FIT protocol 2.0
+const PROTOCOL_VERSION: u8 = 0x20;
+/// FIT profile 21.32
+const PROFILE_VERSION: u16 = 2132;
+const HEADER_SIZE: u8 = 14;
+
+/// Global message numbers
+const MESG_FILE_ID: u16 = 0;
+const MESG_WEIGHT_SCALE: u16 = 30;
+/// `file` type of the weight files
+const FILE_WEIGHT: u32 = 9;
+/// `manufacturer` of the applications without an assigned id
+const MANUFACTURER_DEVELOPMENT: u32 = 255;
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+/// CRC-16 of the FIT header and file
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        let nibble = |crc: u16, n: u8| {
+            let tmp = CRC_TABLE[(crc & 0xF) as usize];
+            let crc = (crc >> 4) & 0x0FFF;
+            crc ^ tmp ^ CRC_TABLE[(n & 0xF) as usize]
+        };
+        nibble(nibble(crc, byte), byte >> 4)
+    })
+}
+
+/// Base types of the fields used by bluescale
+#[derive(Clone, Copy)]
+enum BaseType {
+    Enum,
+    Uint8,
+    Uint16,
+    Uint32,
+}
+
+impl BaseType {
+    fn id(self) -> u8 {
+        match self {
+            BaseType::Enum => 0x00,
+            BaseType::Uint8 => 0x02,
+            BaseType::Uint16 => 0x84,
+            BaseType::Uint32 => 0x86,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            BaseType::Enum | BaseType::Uint8 => 1,
+            BaseType::Uint16 => 2,
+            BaseType::Uint32 => 4,
+        }
+    }
+
+    /// value of an unknown field, the other values are kept below it
+    fn invalid(self) -> u32 {
+        match self {
+            BaseType::Enum | BaseType::Uint8 => 0xFF,
+            BaseType::Uint16 => 0xFFFF,
+            BaseType::Uint32 => 0xFFFF_FFFF,
+        }
+    }
+}
+
+/// Field number and base type of a message definition
+type FieldDef = (u8, BaseType);
+
+const FILE_ID_FIELDS: &[FieldDef] = &[
+    (0, BaseType::Enum),   // type
+    (1, BaseType::Uint16), // manufacturer
+    (2, BaseType::Uint16), // product
+    (4, BaseType::Uint32), // time_created
+];
+
+/// The `weight_scale` fields; bluescale doesn't estimate the metabolic age
+/// and the physique rating, so they are not written
+const WEIGHT_SCALE_FIELDS: &[FieldDef] = &[
+    (253, BaseType::Uint32), // timestamp
+    (0, BaseType::Uint16),   // weight, kg * 100
+    (1, BaseType::Uint16),   // percent_fat, % * 100
+    (2, BaseType::Uint16),   // percent_hydration, % * 100
+    (4, BaseType::Uint16),   // bone_mass, kg * 100
+    (5, BaseType::Uint16),   // muscle_mass, kg * 100
+    (7, BaseType::Uint16),   // basal_met, kcal/day * 4
+    (11, BaseType::Uint8),   // visceral_fat_rating
+    (13, BaseType::Uint16),  // bmi, kg/m² * 10
+];
+
+/// Writer of the definition and data messages
+struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn definition(&mut self, local: u8, global: u16, fields: &[FieldDef]) {
+        // definition record header, reserved byte, little endian architecture
+        self.data.extend([0x40 | local, 0, 0]);
+        self.data.extend(global.to_le_bytes());
+        self.data.push(fields.len() as u8);
+        for (num, base_type) in fields {
+            self.data
+                .extend([*num, base_type.size() as u8, base_type.id()]);
+        }
+    }
+
+    fn message(&mut self, local: u8, fields: &[FieldDef], values: &[Option<u32>]) {
+        self.data.push(local);
+        for ((_, base_type), value) in fields.iter().zip(values) {
+            let invalid = base_type.invalid();
+            let value = value.filter(|v| *v < invalid).unwrap_or(invalid);
+            self.data.extend(&value.to_le_bytes()[..base_type.size()]);
+        }
+    }
+
+    /// The file with the header and the CRCs
+    fn finish(self) -> Vec<u8> {
+        let mut file = vec![HEADER_SIZE, PROTOCOL_VERSION];
+        file.extend(PROFILE_VERSION.to_le_bytes());
+        file.extend((self.data.len() as u32).to_le_bytes());
+        file.extend(b".FIT");
+        file.extend(crc16(&file).to_le_bytes());
+        file.extend(self.data);
+        file.extend(crc16(&file).to_le_bytes());
+        file
+    }
+}
+
+/// Seconds since the FIT epoch of the time (UTC)
+fn timestamp(date_time: NaiveDateTime) -> Option<u32> {
+    u32::try_from(date_time.and_utc().timestamp() - FIT_EPOCH).ok()
+}
+
+/// Scaled value of the field, negative values are unknown
+fn scaled(value: Option<f32>, scale: f32) -> Option<u32> {
+    value
+        .filter(|v| *v >= 0.0)
+        .map(|v| (v * scale).round() as u32)
+}
+
+/// Encode the measurements as a FIT weight file: a `file_id` and a `weight_scale`
+/// message for each measurement, with the calibrated values where there are some
+pub fn encode(measurements: &[Measurement]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let last = measurements.last().ok_or("no measurements")?;
+
+    let mut encoder = Encoder { data: vec![] };
+    encoder.definition(0, MESG_FILE_ID, FILE_ID_FIELDS);
+    encoder.message(
+        0,
+        FILE_ID_FIELDS,
+        &[
+            Some(FILE_WEIGHT),
+            Some(MANUFACTURER_DEVELOPMENT),
+            Some(0),
+            timestamp(last.date_time),
+        ],
+    );
+
+    encoder.definition(1, MESG_WEIGHT_SCALE, WEIGHT_SCALE_FIELDS);
+    for m in measurements {
+        let c = &m.calibrated;
+        encoder.message(
+            1,
+            WEIGHT_SCALE_FIELDS,
+            &[
+                Some(timestamp(m.date_time).ok_or("time before the FIT epoch")?),
+                scaled(Some(m.weight), 100.0),
+                scaled(c.bf.or(m.bf), 100.0),
+                scaled(c.water_rate.or(m.water_rate), 100.0),
+                scaled(c.bone_mass.or(m.bone_mass), 100.0),
+                scaled(c.muscle_kg.or(m.muscle_kg), 100.0),
+                scaled(Some(m.bmr), 4.0),
+                scaled(c.visceral_fat.or(m.visceral_fat), 1.0),
+                scaled(Some(m.bmi), 10.0),
+            ],
+        );
+    }
+
+    Ok(encoder.finish())
+}
+
+/// Name of the file with the measurements of the profile,
+/// eg. `john_20230105-073000.fit` for a single one or `john_20230101-20230131.fit`
+pub fn file_name(p: &Person, measurements: &[Measurement]) -> String {
+    let name = safe_name(&p.name);
+    match measurements {
+        [m] => format!("{}_{}.fit", name, m.date_time.format("%Y%m%d-%H%M%S")),
+        [first, .., last] => format!(
+            "{}_{}-{}.fit",
+            name,
+            first.date_time.format("%Y%m%d"),
+            last.date_time.format("%Y%m%d")
+        ),
+        [] => format!("{}.fit", name),
+    }
+}
+
+/// Write the measurements as a FIT file in the directory, returns its path
+pub fn write_dir(
+    dir: &Path,
+    p: &Person,
+    measurements: &[Measurement],
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let data = encode(measurements)?;
+    let path = dir.join(file_name(p, measurements));
+    fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Composition;
+    use crate::outlier::OutlierFilter;
+    use crate::uncertainty::InputUncertainty;
+    use chrono::NaiveDate;
+
+    fn measurement(date_time: NaiveDateTime) -> Measurement {
+        let p = Person {
+            name: "john".to_string(),
+            sex: 1,
+            birthday: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
+        };
+        let composition = Composition {
+            bf: Some(20.5),
+            ..Default::default()
+        };
+        p.measure_known(date_time, 80.25, &composition, "test")
+    }
+
+    fn time(y: i32, m: u32, d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn crc_check_value() {
+        // the FIT CRC is the CRC-16/ARC, with the check value 0xBB3D
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+        assert_eq!(crc16(b""), 0);
+    }
+
+    #[test]
+    fn header_and_crc() {
+        let file = encode(&[measurement(time(2023, 5, 1, 7))]).unwrap();
+        // the file_id and the weight_scale definitions and messages
+        assert_eq!(file.len(), 14 + 81 + 2);
+        assert_eq!(
+            file[..14],
+            [14, 0x20, 0x54, 0x08, 81, 0, 0, 0, b'.', b'F', b'I', b'T', 0x7D, 0x21]
+        );
+        // the CRC over the data with its CRC appended is 0
+        assert_eq!(crc16(&file), 0);
+    }
+
+    #[test]
+    fn weight_scale_values() {
+        let file = encode(&[measurement(time(2023, 5, 1, 7))]).unwrap();
+        // the weight_scale message follows the file_id definition (18 bytes),
+        // its message (10 bytes) and the weight_scale definition (33 bytes)
+        let message = &file[14 + 18 + 10 + 33..file.len() - 2];
+        assert_eq!(message[0], 1);
+        assert_eq!(message[1..5], 1_051_858_800u32.to_le_bytes());
+        assert_eq!(message[5..7], 8025u16.to_le_bytes());
+        assert_eq!(message[7..9], 2050u16.to_le_bytes());
+        // no water rate
+        assert_eq!(message[9..11], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn fit_epoch() {
+        assert_eq!(timestamp(time(1989, 12, 31, 0)), Some(0));
+        assert_eq!(timestamp(time(1989, 12, 30, 0)), None);
+        assert!(encode(&[]).is_err());
+        assert!(encode(&[measurement(time(1980, 1, 1, 7))]).is_err());
+    }
+
+    #[test]
+    fn file_names() {
+        let mut p = crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 1, 1).unwrap());
+        let (first, last) = (
+            measurement(time(2023, 1, 1, 7)),
+            measurement(time(2023, 1, 31, 7)),
+        );
+        assert_eq!(
+            file_name(&p, std::slice::from_ref(&first)),
+            "test_20230101-070000.fit"
+        );
+        assert_eq!(file_name(&p, &[first, last]), "test_20230101-20230131.fit");
+        // the profile name never leaves the output directory
+        p.name = "../john doe".to_string();
+        assert_eq!(file_name(&p, &[]), "___john_doe.fit");
+    }
+}
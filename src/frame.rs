@@ -0,0 +1,78 @@
+use bitops::BitOps;
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Length of the scale's service data frame
+pub const FRAME_LEN: usize = 13;
+
+/// Measurement broadcast by the scale in its `Service UUID` data
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// raw control bytes (`byte0 << 8 | byte1`)
+    pub flags: u16,
+    pub is_weight_removed: bool,
+    pub is_stabilized: bool,
+    /// timestamp as set in the scale (without timezone)
+    pub date_time: NaiveDateTime,
+    /// weight in the unit of the scale
+    pub weight: f32,
+    /// impedance (Ω), zero if not measured (yet)
+    pub impedance: f32,
+}
+
+impl Frame {
+    /// Decode the service data frame.
+    ///
+    /// Fails on frames which are too short, not stabilised or with an invalid date.
+    pub fn decode(data: &[u8]) -> Result<Frame, Box<dyn std::error::Error>> {
+        if data.len() < FRAME_LEN {
+            return Err(format!("Invalid scale data length: {}", data.len()).into());
+        }
+
+        let ctrl_byte0: u8 = data[0];
+        let ctrl_byte1: u8 = data[1];
+
+        let is_weight_removed: bool = ctrl_byte1.is_bit_set(7);
+        let is_date_invalid: bool = ctrl_byte1.is_bit_set(6);
+        let is_stabilized: bool = ctrl_byte1.is_bit_set(5);
+        let is_lbs_unit: bool = ctrl_byte0.is_bit_set(0);
+        let is_catty_unit: bool = ctrl_byte1.is_bit_set(6);
+        let is_impedance: bool = ctrl_byte1.is_bit_set(1);
+
+        if !is_stabilized || is_date_invalid {
+            return Err("Invalid scale data (eg. not stabilised)".into());
+        }
+
+        let year: u16 = ((data[3] as u16) << 8) + data[2] as u16;
+        let month: u8 = data[4];
+        let day: u8 = data[5];
+        let hours: u8 = data[6];
+        let min: u8 = data[7];
+        let sec: u8 = data[8];
+
+        let mut impedance: f32 = 0.0;
+
+        let weight: f32 = if is_lbs_unit || is_catty_unit {
+            (((data[12] as u16) << 8) + data[11] as u16) as f32 / 100.0
+        } else {
+            (((data[12] as u16) << 8) + data[11] as u16) as f32 / 200.0
+        };
+
+        if is_impedance {
+            impedance = (((data[10] as u16) << 8) + data[9] as u16) as f32;
+        }
+
+        let date_time: NaiveDateTime =
+            NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
+                .and_then(|d| d.and_hms_opt(hours as u32, min as u32, sec as u32))
+                .ok_or("Error: invalid datetime for mi scale data")?;
+
+        Ok(Frame {
+            flags: ((ctrl_byte0 as u16) << 8) + ctrl_byte1 as u16,
+            is_weight_removed,
+            is_stabilized,
+            date_time,
+            weight,
+            impedance,
+        })
+    }
+}
@@ -0,0 +1,170 @@
+use crate::trend::{Sample, Trend};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::fmt;
+use strum::{Display, EnumString};
+
+/// Metric of the goal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString, Serialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GoalMetric {
+    /// target weight (kg)
+    Weight,
+    /// target body fat (%)
+    Fat,
+}
+
+/// Whether the value has to decrease or increase to reach the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString, Serialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GoalDirection {
+    Lose,
+    Gain,
+}
+
+/// Per-profile goal, eg. `goal_weight = 75` with `goal_weight_direction = lose`
+/// in the `[profile]` section
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Goal {
+    pub metric: GoalMetric,
+    pub target: f32,
+    pub direction: GoalDirection,
+}
+
+/// How far the person is from the goal at some measurement
+pub struct Progress {
+    pub goal: Goal,
+    pub date_time: NaiveDateTime,
+    /// current value: trend weight or the measured body fat
+    pub current: f32,
+    /// remaining change to reach the target (signed)
+    pub remaining: f32,
+    /// the target is reached or passed in the direction of the goal
+    pub reached: bool,
+    /// current rate of change per week
+    pub weekly_rate: Option<f32>,
+    /// projected date of reaching the target, if the trend is heading towards it
+    pub projected: Option<NaiveDate>,
+}
+
+/// Progress within this distance of the target counts as reached
+const REACHED_TOLERANCE: f32 = 0.05;
+
+impl Goal {
+    /// Progress at the sample using its trend (`None` if the body fat isn't estimated)
+    pub fn progress(&self, sample: &Sample, trend: &Trend) -> Option<Progress> {
+        let (current, weekly_rate) = match self.metric {
+            GoalMetric::Weight => (trend.weight, trend.weight_rate),
+            GoalMetric::Fat => (sample.fat?, trend.fat_rate),
+        };
+        let remaining = self.target - current;
+        let reached = match self.direction {
+            GoalDirection::Lose => remaining >= -REACHED_TOLERANCE,
+            GoalDirection::Gain => remaining <= REACHED_TOLERANCE,
+        };
+
+        let projected = if reached {
+            Some(sample.date_time.date())
+        } else {
+            weekly_rate
+                .filter(|rate| rate.signum() == remaining.signum() && *rate != 0.0)
+                .map(|rate| {
+                    let days = (remaining / rate * 7.0).round() as i64;
+                    sample.date_time.date() + Duration::days(days)
+                })
+        };
+
+        Some(Progress {
+            goal: *self,
+            date_time: sample.date_time,
+            current,
+            remaining,
+            reached,
+            weekly_rate,
+            projected,
+        })
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.goal.metric {
+            GoalMetric::Weight => "kg",
+            GoalMetric::Fat => "%",
+        };
+        write!(
+            f,
+            "goal {}: {} {}, current: {:.2} {}, remaining: {:+.2} {}, ",
+            self.goal.metric, self.goal.target, unit, self.current, unit, self.remaining, unit
+        )?;
+        match self.projected {
+            _ if self.reached => write!(f, "reached"),
+            Some(date) => write!(f, "projected: {}", date),
+            None => write!(f, "projected: never at the current trend"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(direction: GoalDirection, weight: f32, rate: f32) -> Progress {
+        let goal = Goal {
+            metric: GoalMetric::Weight,
+            target: 75.0,
+            direction,
+        };
+        let date_time = NaiveDate::from_ymd_opt(2023, 1, 1)
+            .unwrap()
+            .and_hms_opt(7, 0, 0)
+            .unwrap();
+        let sample = Sample {
+            date_time,
+            weight,
+            fat: None,
+            muscle: None,
+            impedance: None,
+            bmr: Some(1700.0),
+            trend_weight: None,
+        };
+        let trend = Trend {
+            weight,
+            weight_avg_7d: weight,
+            weight_avg_30d: weight,
+            weight_rate: Some(rate),
+            fat_rate: None,
+            muscle_rate: None,
+        };
+        goal.progress(&sample, &trend).unwrap()
+    }
+
+    #[test]
+    fn projected_towards_the_target() {
+        let pr = progress(GoalDirection::Lose, 77.0, -0.5);
+        assert!(!pr.reached);
+        assert_eq!(pr.projected, NaiveDate::from_ymd_opt(2023, 1, 29));
+    }
+
+    #[test]
+    fn never_away_from_the_target() {
+        let pr = progress(GoalDirection::Lose, 77.0, 0.5);
+        assert!(!pr.reached);
+        assert_eq!(pr.projected, None);
+    }
+
+    #[test]
+    fn reached_past_the_target() {
+        let pr = progress(GoalDirection::Lose, 74.0, -0.5);
+        assert!(pr.reached);
+        assert!(pr.to_string().ends_with("reached"));
+
+        let pr = progress(GoalDirection::Gain, 76.0, 0.5);
+        assert!(pr.reached);
+        let pr = progress(GoalDirection::Gain, 74.0, -0.5);
+        assert!(!pr.reached);
+        assert_eq!(pr.projected, None);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::body::{Composition, Person};
+use crate::storage::{Storage, StoredReading};
+use crate::trend::Trend;
+use chrono::{NaiveDateTime, Timelike};
+use simplelog::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use strum::{Display, EnumString};
+
+/// Format of the imported measurements
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ImportFormat {
+    Csv,
+    Openscale,
+    Zepp,
+}
+
+impl ImportFormat {
+    /// Source of the imported values (without the impedance) stored as the algorithm
+    pub fn source(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Openscale => "openscale",
+            ImportFormat::Zepp => "zepp",
+        }
+    }
+
+    /// Time of a measurement compared to the stored ones when skipping the duplicates:
+    /// the openScale backup has the times to the minute only
+    pub fn dedup_time(&self, date_time: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            ImportFormat::Openscale => date_time
+                .with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(date_time),
+            _ => date_time,
+        }
+    }
+}
+
+/// Time formats accepted in the imported files
+const TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Parse the time of an imported measurement
+pub fn parse_time(value: &str) -> Result<NaiveDateTime, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    TIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())
+        .ok_or_else(|| format!("invalid time: `{}`", value).into())
+}
+
+/// Measurement read from an imported file
+pub struct Imported {
+    /// profile of the measurement, if the file has one
+    pub profile: Option<String>,
+    /// raw inputs, the impedance is 0 when the file doesn't have it
+    pub reading: StoredReading,
+    /// body composition values of the file, used without the impedance
+    pub composition: Composition,
+}
+
+/// Read the measurements from a CSV file with a header, such as the one written by
+/// `bluescale export`: the `time` and `weight` columns are required, the `impedance`,
+/// `flags` and `profile` are optional. Without the impedance the body composition values
+/// are read from the `fat_rate`, `body_water_rate`, `muscle_kg`, `bone_mass`,
+/// `visceral_fat` and `metabolism` columns.
+///
+/// The `columns` maps these names to the headers of the file when they differ.
+pub fn read_csv<R: Read>(
+    reader: R,
+    columns: &HashMap<String, String>,
+) -> Result<Vec<Imported>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let index = |name: &str| {
+        let header = columns.get(name).map_or(name, |h| h.as_str());
+        headers.iter().position(|h| h == header)
+    };
+    let required = |name: &str| index(name).ok_or(format!("missing column `{}`", name));
+    let time = required("time")?;
+    let weight = required("weight")?;
+    let impedance = index("impedance");
+    let flags = index("flags");
+    let profile = index("profile");
+    let composition = [
+        "fat_rate",
+        "body_water_rate",
+        "muscle_kg",
+        "bone_mass",
+        "visceral_fat",
+        "metabolism",
+    ]
+    .map(index);
+
+    let mut imported = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |i: usize| record.get(i).unwrap_or_default();
+        // empty or missing values are unknown
+        let value = |i: Option<usize>| -> Result<Option<f32>, Box<dyn std::error::Error>> {
+            match i.map(field) {
+                Some(v) if !v.is_empty() => Ok(Some(v.parse()?)),
+                _ => Ok(None),
+            }
+        };
+        let parse = || -> Result<Imported, Box<dyn std::error::Error>> {
+            let [bf, water_rate, muscle_kg, bone_mass, visceral_fat, bmr] = composition;
+            Ok(Imported {
+                profile: profile
+                    .map(field)
+                    .filter(|profile| !profile.is_empty())
+                    .map(String::from),
+                reading: StoredReading {
+                    date_time: parse_time(field(time))?,
+                    weight: field(weight).parse()?,
+                    impedance: value(impedance)?.unwrap_or_default(),
+                    flags: match flags.map(field) {
+                        Some(flags) if !flags.is_empty() => flags.parse()?,
+                        _ => 0,
+                    },
+                },
+                composition: Composition {
+                    water_rate: value(water_rate)?,
+                    bmr: value(bmr)?,
+                    visceral_fat: value(visceral_fat)?,
+                    bf: value(bf)?,
+                    muscle_kg: value(muscle_kg)?,
+                    bone_mass: value(bone_mass)?,
+                },
+            })
+        };
+        // the header is the line 1
+        imported.push(parse().map_err(|e| format!("line {}: {}", line + 2, e))?);
+    }
+
+    Ok(imported)
+}
+
+/// Result of the import
+pub struct ImportSummary {
+    pub stored: usize,
+    /// already stored (or repeated in the file)
+    pub duplicates: usize,
+    /// measurements of another profile
+    pub skipped: usize,
+}
+
+/// Store the imported measurements of the profile, skipping the ones already stored,
+/// and update the trends of the whole history.
+///
+/// The body composition is computed from the impedance when there is one,
+/// otherwise the values of the file are stored with the source of the `format` as the algorithm.
+pub async fn import(
+    db: &dyn Storage,
+    p: &Person,
+    imported: Vec<Imported>,
+    format: ImportFormat,
+) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+    let mut summary = ImportSummary {
+        stored: 0,
+        duplicates: 0,
+        skipped: 0,
+    };
+    let mut known: HashSet<NaiveDateTime> = db
+        .fetch_samples(&p.name, None)
+        .await?
+        .iter()
+        .map(|s| format.dedup_time(s.date_time))
+        .collect();
+
+    let mut readings = vec![];
+    for i in imported {
+        if i.profile.as_ref().is_some_and(|profile| *profile != p.name) {
+            summary.skipped += 1;
+        } else if !known.insert(format.dedup_time(i.reading.date_time)) {
+            summary.duplicates += 1;
+        } else {
+            readings.push(i);
+        }
+    }
+    readings.sort_by_key(|i| i.reading.date_time);
+
+    let calibration = db.fetch_calibration(&p.name).await?;
+    for i in &readings {
+        let r = &i.reading;
+        let m = if r.impedance > 0.0 {
+            let mut m = p.measure(r.date_time, r.weight, r.impedance, r.flags);
+            m.calibrated = calibration.apply(&m);
+            m
+        } else {
+            p.measure_known(r.date_time, r.weight, &i.composition, format.source())
+        };
+        db.insert_measurement(&m, p, None).await?;
+        summary.stored += 1;
+    }
+
+    if summary.stored > 0 {
+        let samples = db.fetch_samples(&p.name, None).await?;
+        let trends = Trend::series(&samples);
+        let updated = db.update_trends(&p.name, &samples, &trends).await?;
+        info!("🛢️  Updated trends of {} measurements", updated);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(file: &str, columns: &[(&str, &str)]) -> Result<Vec<Imported>, String> {
+        let columns = columns
+            .iter()
+            .map(|(name, header)| (name.to_string(), header.to_string()))
+            .collect();
+        read_csv(file.as_bytes(), &columns).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn read_export_columns() {
+        let file = "\
+time,profile,weight,impedance,flags,fat_rate
+2023-01-05 07:30:00,john,80.5,512,2,
+2023-01-06T07:31,,80.1,,,21.5
+";
+        let imported = read(file, &[]).unwrap();
+        assert_eq!(imported.len(), 2);
+
+        let i = &imported[0];
+        assert_eq!(i.profile.as_deref(), Some("john"));
+        assert_eq!(i.reading.date_time, parse_time("2023-01-05 07:30").unwrap());
+        assert_eq!(i.reading.weight, 80.5);
+        assert_eq!(i.reading.impedance, 512.0);
+        assert_eq!(i.reading.flags, 2);
+        assert_eq!(i.composition.bf, None);
+
+        let i = &imported[1];
+        assert_eq!(i.profile, None);
+        assert_eq!(
+            i.reading.date_time,
+            parse_time("2023-01-06 07:31:00").unwrap()
+        );
+        assert_eq!(i.reading.impedance, 0.0);
+        assert_eq!(i.reading.flags, 0);
+        assert_eq!(i.composition.bf, Some(21.5));
+        assert_eq!(i.composition.muscle_kg, None);
+    }
+
+    #[test]
+    fn read_mapped_columns() {
+        let file = "Date,Weight (kg),Body Fat\n2023-01-05 07:30,80.5,20.1\n";
+        let imported = read(
+            file,
+            &[
+                ("time", "Date"),
+                ("weight", "Weight (kg)"),
+                ("fat_rate", "Body Fat"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(imported[0].reading.weight, 80.5);
+        assert_eq!(imported[0].composition.bf, Some(20.1));
+    }
+
+    #[test]
+    fn read_errors() {
+        let error = |file| read(file, &[]).err().unwrap();
+        assert_eq!(error("weight\n80\n"), "missing column `time`");
+        assert_eq!(error("time\n2023-01-05 07:30\n"), "missing column `weight`");
+        assert!(error("time,weight\n2023-01-05 07:30,80\n05.01.2023,80\n")
+            .starts_with("line 3: invalid time"));
+        assert!(error("time,weight\n2023-01-05 07:30,\n").starts_with("line 2:"));
+    }
+
+    #[test]
+    fn dedup_to_the_minute() {
+        let t = parse_time("2023-01-05 07:30:42").unwrap();
+        assert_eq!(
+            ImportFormat::Openscale.dedup_time(t),
+            parse_time("2023-01-05 07:30").unwrap()
+        );
+        assert_eq!(ImportFormat::Csv.dedup_time(t), t);
+        assert_eq!(ImportFormat::Zepp.dedup_time(t), t);
+    }
+}
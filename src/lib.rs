@@ -0,0 +1,82 @@
+//! Reading the *Xiaomi Mi Body Composition Scale 2* and computing the body composition.
+//!
+//! The crate is used by the `bluescale` daemon, but the building blocks can be reused:
+//! - [`frame`] - decoding of the data frame broadcast by the scale,
+//! - [`body`] - [`Person`](body::Person) profile and the computed [`Measurement`](body::Measurement),
+//! - [`algorithm`] - the body composition formulas (MiFit and published BIA equations),
+//! - [`calibration`] - per-profile linear corrections fitted to reference measurements,
+//! - [`trend`] - smoothed trend weight, moving averages and rates of change,
+//! - [`goal`] - goal tracking with the projected date of reaching the target,
+//! - [`rating`] - age-appropriate rating ranges and the BMI-for-age of children,
+//! - [`uncertainty`] - confidence intervals of the computed values,
+//! - [`outlier`] - rejection of implausible measurements (eg. guests),
+//! - [`energy`] - total daily energy expenditure and energy balance estimation,
+//! - [`storage`] - storing and reading the measurements, in PostgreSQL ([`database`])
+//!   or SQLite ([`sqlite`]),
+//! - [`migration`] - versioned migrations of the database schema,
+//! - [`export`] and [`import`] - exporting and importing the measurement history (CSV),
+//!   [`openscale`] - the openScale CSV backup, [`zepp`] - the Zepp Life data export,
+//!   [`fit`] - Garmin FIT weight files, [`fhir`] - HL7 FHIR Observations,
+//! - [`sink`] - destinations of the measurements (the storage, a CSV log, FIT files,
+//!   JSON Lines with the [`omh`] Open mHealth records, [`mqtt`] with Home Assistant discovery),
+//! - [`spool`] - on-disk spool of the measurements while a sink is unreachable,
+//! - [`bluetooth`] - querying the scale with [bluer](https://crates.io/crates/bluer),
+//! - [`config`] - reading the `bluescale.conf` sections.
+//!
+//! ```
+//! use bluescale::body::Person;
+//! use bluescale::frame::Frame;
+//! use chrono::NaiveDate;
+//!
+//! let p = Person {
+//!     name: "john".to_string(),
+//!     sex: 1,
+//!     birthday: NaiveDate::from_ymd_opt(1980, 1, 1).unwrap(),
+//!     height: 180.0,
+//!     algorithms: vec![],
+//!     goals: vec![],
+//!     outlier: Default::default(),
+//!     activity: 1.2,
+//!     uncertainty: Default::default(),
+//! };
+//! // service data of a stabilised weigh-in with the impedance: 75.3 kg, 500 Ω
+//! // on 2022-01-15 08:14:36
+//! let data = [
+//!     0x02, 0xa6, 0xe6, 0x07, 0x01, 0x0f, 0x08, 0x0e, 0x24, 0xf4, 0x01, 0xd4, 0x3a,
+//! ];
+//! let frame = Frame::decode(&data)?;
+//! assert_eq!(frame.weight, 75.3);
+//! assert_eq!(frame.impedance, 500.0);
+//!
+//! let m = p.measure(frame.date_time, frame.weight, frame.impedance, frame.flags);
+//! assert_eq!(m.age, 42.0 + 14.0 / 365.0);
+//! println!("{}", m);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod algorithm;
+pub mod bluetooth;
+pub mod body;
+pub mod calibration;
+pub mod config;
+pub mod database;
+pub mod energy;
+pub mod export;
+pub mod fhir;
+pub mod fit;
+pub mod frame;
+pub mod goal;
+pub mod import;
+pub mod migration;
+pub mod mqtt;
+pub mod omh;
+pub mod openscale;
+pub mod outlier;
+pub mod rating;
+pub mod sink;
+pub mod spool;
+pub mod sqlite;
+pub mod storage;
+pub mod trend;
+pub mod uncertainty;
+pub mod zepp;
@@ -1,23 +1,38 @@
 use beep::beep;
 use bluer::{AdapterEvent, Address};
-use chrono::{NaiveDate, Utc};
-use clap::Parser;
+use chrono::{Duration, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
 use futures::{pin_mut, StreamExt};
 use ini::Ini;
 use simplelog::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::rc::Rc;
 use std::{thread, time};
-use tokio::time::sleep;
+use tokio::time::{sleep, Instant};
 
-mod bluetooth;
-mod body;
-mod database;
-use crate::bluetooth::query_device;
-use crate::body::Person;
-use crate::database::Database;
+use bluescale::bluetooth::{flush_spool, query_device};
+use bluescale::body::Person;
+use bluescale::calibration::Metric;
+use bluescale::config::{
+    config_json_lines_stdout, config_read_profile, config_read_sinks, config_read_spool,
+    config_read_storage, config_read_uncertainty,
+};
+use bluescale::energy::{week_start, EnergyBalance};
+use bluescale::export::{fetch_history, write_csv, ExportFormat};
+use bluescale::import::{read_csv, ImportFormat};
+use bluescale::sink::SinkSpool;
+use bluescale::storage::Storage;
+use bluescale::trend::Trend;
+use bluescale::{fhir, fit, openscale, zepp};
 
-pub const SECS_PER_YEAR: u32 = 31557600;
+/// How often the daemon checks the spool, and the longest delay between
+/// the reconnection attempts
+const SPOOL_RETRY_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// First delay of the reconnection backoff, doubled after each failed attempt
+const RECONNECT_BACKOFF: time::Duration = time::Duration::from_secs(2);
 
 /// Simple program to read `Xiaomi Mi Body Composition Scale 2` via bluetooth
 /// and store measurement in the configured PostgreSQL database
@@ -31,9 +46,189 @@ struct Args {
     /// Config file path
     #[clap(short, long, parse(from_os_str), default_value = "/etc/bluescale.conf")]
     config: std::path::PathBuf,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-run the body composition formulas over the stored measurements
+    /// and update the derived columns
+    Recompute {
+        /// Only measurements since this date (YYYY-MM-DD)
+        #[clap(long)]
+        since: Option<NaiveDate>,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Report the smoothed trend weight, moving averages and weekly rates of change
+    Trend {
+        /// Number of days to report
+        #[clap(long, default_value = "30")]
+        days: i64,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+
+        /// Recompute the trends of the whole history and update the stored values
+        #[clap(long)]
+        update: bool,
+    },
+    /// Report the total daily energy expenditure and the energy balance
+    Energy {
+        /// Only measurements since this date (YYYY-MM-DD), defaults to the last 4 weeks
+        #[clap(long)]
+        since: Option<NaiveDate>,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+
+        /// Store the weekly figures of the reported period
+        #[clap(long)]
+        update: bool,
+    },
+    /// Manage the reference measurements (eg. DEXA) used for the calibration
+    Calibration {
+        #[clap(subcommand)]
+        action: CalibrationAction,
+    },
+    /// Manage the measurements rejected as outliers (eg. guests)
+    Quarantine {
+        #[clap(subcommand)]
+        action: QuarantineAction,
+    },
+    /// Manage the database schema
+    Db {
+        #[clap(subcommand)]
+        action: DbAction,
+    },
+    /// Inspect or deliver the measurements waiting in the spool
+    Spool {
+        #[clap(subcommand)]
+        action: SpoolAction,
+    },
+    /// Export the stored measurements with all the computed values
+    Export {
+        /// Output format: csv, openscale, fit or fhir
+        #[clap(long, default_value = "csv")]
+        format: ExportFormat,
+
+        /// Only measurements since this date (YYYY-MM-DD)
+        #[clap(long)]
+        since: Option<NaiveDate>,
+
+        /// Only measurements until this date (YYYY-MM-DD)
+        #[clap(long)]
+        until: Option<NaiveDate>,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+
+        /// Recompute the values from the raw scale inputs with the current profile
+        /// and calibration instead of exporting the stored ones
+        #[clap(long)]
+        recompute: bool,
+
+        /// Output file or directory (for fit), defaults to the standard output
+        #[clap(short, long, parse(from_os_str))]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Import measurements from a file, skipping the ones already stored
+    Import {
+        /// Input format: csv, openscale or zepp
+        format: ImportFormat,
+
+        /// Input file (for zepp the unpacked data export directory)
+        #[clap(parse(from_os_str))]
+        file: std::path::PathBuf,
+
+        /// Column of the file, eg. `--column weight="Weight (kg)"`
+        #[clap(long = "column")]
+        columns: Vec<String>,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Report the progress towards the configured goals
+    Goals {
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CalibrationAction {
+    /// Add a reference measurement
+    Add {
+        /// Date of the reference measurement (YYYY-MM-DD)
+        #[clap(long)]
+        date: NaiveDate,
+
+        /// Metric: fat, muscle, water, bone or visceral_fat
+        #[clap(long)]
+        metric: Metric,
+
+        /// Reference value (%, kg or rating)
+        #[clap(long)]
+        value: f32,
+
+        /// Source of the reference, eg. `dexa` or `caliper`
+        #[clap(long, default_value = "dexa")]
+        source: String,
+
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// List the reference measurements and the fitted corrections
+    List {
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Delete the reference measurement
+    Delete { id: i32 },
 }
 
-fn logging_init(debug: bool) {
+#[derive(Subcommand, Debug)]
+enum QuarantineAction {
+    /// List the quarantined measurements
+    List {
+        /// Profile name (defaults to the configured profile)
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Move the quarantined measurement to the main series
+    Accept { id: i32 },
+    /// Delete the quarantined measurement
+    Delete { id: i32 },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbAction {
+    /// Create or upgrade the schema by applying the pending migrations
+    Migrate,
+    /// Show the schema version and the pending migrations
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum SpoolAction {
+    /// List the spooled measurements
+    Status,
+    /// Deliver the spooled measurements to the sinks now
+    Flush,
+}
+
+fn logging_init(debug: bool, mode: TerminalMode) {
     let conf = ConfigBuilder::new()
         .set_time_format("%F, %H:%M:%S%.3f".to_string())
         .set_write_log_enable_colors(true)
@@ -48,7 +243,7 @@ fn logging_init(debug: bool) {
             LevelFilter::Info
         },
         conf.clone(),
-        TerminalMode::Mixed,
+        mode,
         ColorChoice::Auto,
     );
     loggers.push(console_logger);
@@ -56,55 +251,468 @@ fn logging_init(debug: bool) {
     CombinedLogger::init(loggers).expect("Cannot initialize logging subsystem");
 }
 
-fn config_read_postgres(conf: Ini) -> Result<Database, Box<dyn std::error::Error>> {
-    match conf.section(Some("postgres".to_owned())) {
-        Some(section) => Ok(Database {
-            name: "🦏 postgres".to_string(),
-            host: section.get("host").ok_or("missing `host`")?.to_string(),
-            dbname: section.get("dbname").ok_or("missing `dbname`")?.to_string(),
-            username: section
-                .get("username")
-                .ok_or("missing `username`")?
-                .to_string(),
-            password: section
-                .get("password")
-                .ok_or("missing `password`")?
-                .to_string(),
-        }),
-        None => Err("missing [postgres] config section")?,
-    }
-}
-
-fn config_read_profile(conf: Ini) -> Result<Person, Box<dyn std::error::Error>> {
-    match conf.section(Some("profile".to_owned())) {
-        Some(section) => {
-            //computing age:
-            let date_str = section.get("birthday").ok_or("missing `birthday`")?;
-            let birthday = NaiveDate::parse_from_str(date_str, "%Y-%m-%d");
-            if let Err(e) = birthday {
-                return Err(format!("error parsing `birthday`: {}", e).into());
+fn check_user(p: &Person, user: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    match user {
+        Some(user) if user != p.name => {
+            Err(format!("no profile configured for user `{}`", user).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn recompute(
+    db: &dyn Storage,
+    p: &Person,
+    since: Option<NaiveDate>,
+    user: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+
+    let calibration = db.fetch_calibration(&p.name).await?;
+    let readings = db.fetch_readings(&p.name, since).await?;
+    info!(
+        "🔁 Recomputing {} measurements of `{}`",
+        readings.len(),
+        p.name
+    );
+    let measurements: Vec<_> = readings
+        .iter()
+        .map(|r| {
+            let mut m = p.measure(r.date_time, r.weight, r.impedance, r.flags);
+            m.calibrated = calibration.apply(&m);
+            m
+        })
+        .collect();
+    let updated = db.update_derived(&measurements, p).await?;
+    info!("🛢️  Updated {} measurements", updated);
+
+    Ok(())
+}
+
+async fn trend(
+    db: &dyn Storage,
+    p: &Person,
+    days: i64,
+    user: Option<String>,
+    update: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+
+    let samples = db.fetch_samples(&p.name, None).await?;
+    let trends = Trend::series(&samples);
+    if update {
+        let updated = db.update_trends(&p.name, &samples, &trends).await?;
+        info!("🛢️  Updated trends of {} measurements", updated);
+    }
+
+    let since = Utc::now().naive_utc() - Duration::days(days);
+    println!(
+        "{:<19} {:>7} {:>7} {:>7} {:>7} {:>9} {:>9} {:>9}",
+        "time", "weight", "trend", "avg7d", "avg30d", "kg/week", "fat/week", "mus/week"
+    );
+    let rate = |r: Option<f32>| r.map(|r| format!("{:+.2}", r)).unwrap_or_default();
+    for (s, t) in samples.iter().zip(&trends) {
+        if s.date_time < since {
+            continue;
+        }
+        println!(
+            "{:<19} {:>7.2} {:>7.2} {:>7.2} {:>7.2} {:>9} {:>9} {:>9}",
+            s.date_time.format("%Y-%m-%d %H:%M:%S"),
+            s.weight,
+            t.weight,
+            t.weight_avg_7d,
+            t.weight_avg_30d,
+            rate(t.weight_rate),
+            rate(t.fat_rate),
+            rate(t.muscle_rate)
+        );
+    }
+
+    Ok(())
+}
+
+async fn goals(
+    db: &dyn Storage,
+    p: &Person,
+    user: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+    if p.goals.is_empty() {
+        return Err("no goals configured (eg. `goal_weight` in the [profile] section)".into());
+    }
+
+    let samples = db.fetch_samples(&p.name, None).await?;
+    let trends = Trend::series(&samples);
+    match samples.last().zip(trends.last()) {
+        Some((sample, trend)) => {
+            println!("last measurement: {}", sample.date_time);
+            for goal in &p.goals {
+                match goal.progress(sample, trend) {
+                    Some(progress) => println!("{}", progress),
+                    None => println!("goal {}: no value measured", goal.metric),
+                }
             }
-            let d = Utc::now().date_naive() - birthday?;
-            let age = d.num_seconds() as f32 / SECS_PER_YEAR as f32;
-            debug!("Age calculated from {:?} birthday: {:?}", date_str, age);
+            Ok(())
+        }
+        None => Err(format!("no measurements stored for `{}`", p.name).into()),
+    }
+}
+
+async fn energy(
+    db: &dyn Storage,
+    p: &Person,
+    since: Option<NaiveDate>,
+    user: Option<String>,
+    update: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+
+    let since = since.unwrap_or_else(|| week_start(Utc::now().date_naive()) - Duration::weeks(4));
+    let samples = db
+        .fetch_samples(&p.name, since.and_hms_opt(0, 0, 0))
+        .await?;
+    let weeks = EnergyBalance::weekly(&samples, p.activity);
+    for (_, e) in &weeks {
+        println!("{}", e);
+    }
+    match EnergyBalance::estimate(&samples, p.activity) {
+        Some(e) => println!("\nwhole period: {}", e),
+        None => println!("not enough measurements since {}", since),
+    }
+
+    if update {
+        db.upsert_energy_weekly(&p.name, &weeks).await?;
+        info!("🛢️  Stored {} weekly figures", weeks.len());
+    }
+
+    Ok(())
+}
+
+async fn calibration(
+    db: &dyn Storage,
+    p: &Person,
+    action: CalibrationAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        CalibrationAction::Add {
+            date,
+            metric,
+            value,
+            source,
+            user,
+        } => {
+            check_user(p, user)?;
+            db.insert_reference(&p.name, date, metric, value, &source)
+                .await?;
+            info!(
+                "📏 Reference {} {} ({}) added, run `bluescale recompute` to calibrate the stored measurements",
+                metric, value, source
+            );
+        }
+        CalibrationAction::List { user } => {
+            check_user(p, user)?;
+            println!(
+                "{:>6} {:<10} {:<12} {:>8}  source",
+                "id", "date", "metric", "value"
+            );
+            for r in db.fetch_references(&p.name).await? {
+                println!(
+                    "{:>6} {:<10} {:<12} {:>8.2}  {}",
+                    r.id,
+                    r.date,
+                    r.metric.to_string(),
+                    r.value,
+                    r.source
+                );
+            }
+            println!();
+            for (metric, correction) in db.fetch_calibration(&p.name).await?.corrections {
+                println!("{}: {}", metric, correction);
+            }
+        }
+        CalibrationAction::Delete { id } => match db.delete_reference(id).await? {
+            0 => return Err(format!("no reference measurement with id {}", id).into()),
+            _ => info!("🗑️  Reference measurement {} deleted", id),
+        },
+    }
+
+    Ok(())
+}
+
+async fn quarantine(
+    db: &dyn Storage,
+    p: &Person,
+    action: QuarantineAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        QuarantineAction::List { user } => {
+            check_user(p, user)?;
+            println!(
+                "{:>6} {:<19} {:>7} {:>9}  reason",
+                "id", "time", "weight", "impedance"
+            );
+            for q in db.fetch_quarantine(&p.name).await? {
+                println!(
+                    "{:>6} {:<19} {:>7.2} {:>9}  {}",
+                    q.id,
+                    q.date_time.format("%Y-%m-%d %H:%M:%S"),
+                    q.weight,
+                    q.impedance,
+                    q.reason
+                );
+            }
+        }
+        QuarantineAction::Accept { id } => match db.accept_quarantined(id).await? {
+            0 => return Err(format!("no quarantined measurement with id {}", id).into()),
+            _ => info!(
+                "✅ Measurement {} accepted, run `bluescale trend --update` to update the trends",
+                id
+            ),
+        },
+        QuarantineAction::Delete { id } => match db.delete_quarantined(id).await? {
+            0 => return Err(format!("no quarantined measurement with id {}", id).into()),
+            _ => info!("🗑️  Measurement {} deleted", id),
+        },
+    }
+
+    Ok(())
+}
+
+async fn migrate(db: &dyn Storage) -> Result<(), Box<dyn std::error::Error>> {
+    let applied = db.migrate().await?;
+    match applied.last() {
+        Some(version) => info!(
+            "🛢️  Applied {} migrations, schema version: {}",
+            applied.len(),
+            version
+        ),
+        None => info!("🛢️  Schema is up to date (version {})", db.latest_version()),
+    }
+
+    Ok(())
+}
+
+async fn db_status(db: &dyn Storage) -> Result<(), Box<dyn std::error::Error>> {
+    let version = db.schema_version().await?;
+    println!("schema version: {}", version);
+    for m in db.migrations().iter().filter(|m| m.version > version) {
+        println!("pending migration {}: {}", m.version, m.description);
+    }
+
+    Ok(())
+}
+
+fn spool_status(sinks: &[SinkSpool]) -> Result<(), Box<dyn std::error::Error>> {
+    for s in sinks {
+        let entries = s.spool.entries()?;
+        println!(
+            "{}: {} measurements in {}",
+            s.sink.name(),
+            entries.len(),
+            s.spool.dir.display()
+        );
+        if !entries.is_empty() {
+            println!("{:<19} {:>7} {:>9}  profile", "time", "weight", "impedance");
+        }
+        for e in entries {
+            println!(
+                "{:<19} {:>7.2} {:>9}  {}",
+                e.reading.date_time.format("%Y-%m-%d %H:%M:%S"),
+                e.reading.weight,
+                e.reading.impedance,
+                e.profile
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of the measurements waiting in the spools
+fn spool_pending(sinks: &[SinkSpool]) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut pending = 0;
+    for s in sinks {
+        pending += s.spool.entries()?.len();
+    }
+
+    Ok(pending)
+}
+
+async fn spool_flush(
+    db: &dyn Storage,
+    sinks: &[SinkSpool],
+    p: &Person,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let delivered = flush_spool(sinks, db, p).await?;
+    info!("📤 Delivered {} spooled measurements", delivered);
+
+    Ok(())
+}
+
+async fn export(
+    db: &dyn Storage,
+    p: &Person,
+    format: ExportFormat,
+    (since, until): (Option<NaiveDate>, Option<NaiveDate>),
+    user: Option<String>,
+    recompute: bool,
+    output: Option<std::path::PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+
+    let measurements = fetch_history(db, p, (since, until), recompute).await?;
+    let output = match output {
+        // FIT files are named by the profile and the dates in the directory
+        Some(dir) if format == ExportFormat::Fit && dir.is_dir() => {
+            Some(dir.join(fit::file_name(p, &measurements)))
+        }
+        output => output,
+    };
+    let mut writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    match format {
+        ExportFormat::Csv => write_csv(writer, p, &measurements)?,
+        ExportFormat::Openscale => openscale::write_csv(writer, &measurements)?,
+        ExportFormat::Fit => writer.write_all(&fit::encode(&measurements)?)?,
+        ExportFormat::Fhir => fhir::write_json(writer, p, &measurements)?,
+    }
+    if let Some(path) = output {
+        info!(
+            "📦 Exported {} measurements to {}",
+            measurements.len(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+async fn import(
+    db: &dyn Storage,
+    p: &Person,
+    format: ImportFormat,
+    file: std::path::PathBuf,
+    columns: Vec<String>,
+    user: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_user(p, user)?;
+
+    let mut mapping = HashMap::new();
+    for column in columns {
+        match column.split_once('=') {
+            Some((name, header)) => mapping.insert(name.to_string(), header.to_string()),
+            None => return Err(format!("invalid column mapping: `{}`", column).into()),
+        };
+    }
+    let open = || File::open(&file).map_err(|e| format!("{}: {}", file.display(), e));
+    let imported = match format {
+        ImportFormat::Csv => read_csv(open()?, &mapping),
+        ImportFormat::Openscale => openscale::read_csv(open()?),
+        ImportFormat::Zepp => zepp::read_export(&file),
+    }
+    .map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    info!(
+        "📥 Importing {} measurements from {}",
+        imported.len(),
+        file.display()
+    );
+    let summary = bluescale::import::import(db, p, imported, format).await?;
+    info!(
+        "🛢️  Stored {} measurements, {} duplicates skipped",
+        summary.stored, summary.duplicates
+    );
+    if summary.skipped > 0 {
+        warn!(
+            "Skipped {} measurements of other profiles than `{}`",
+            summary.skipped, p.name
+        );
+    }
+
+    Ok(())
+}
 
-            Ok(Person {
-                sex: section.get("sex").ok_or("missing `sex`")?.parse()?,
-                age,
-                height: section.get("height").ok_or("missing `height`")?.parse()?,
-            })
+async fn run_command(
+    command: Command,
+    db: &dyn Storage,
+    sinks: &[SinkSpool],
+    p: &Person,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Recompute { since, user } => recompute(db, p, since, user).await,
+        Command::Trend { days, user, update } => trend(db, p, days, user, update).await,
+        Command::Goals { user } => goals(db, p, user).await,
+        Command::Energy {
+            since,
+            user,
+            update,
+        } => energy(db, p, since, user, update).await,
+        Command::Calibration { action } => calibration(db, p, action).await,
+        Command::Quarantine { action } => quarantine(db, p, action).await,
+        Command::Db { action } => match action {
+            DbAction::Migrate => migrate(db).await,
+            DbAction::Status => db_status(db).await,
+        },
+        Command::Export {
+            format,
+            since,
+            until,
+            user,
+            recompute,
+            output,
+        } => export(db, p, format, (since, until), user, recompute, output).await,
+        Command::Import {
+            format,
+            file,
+            columns,
+            user,
+        } => import(db, p, format, file, columns, user).await,
+        Command::Spool { action } => match action {
+            SpoolAction::Status => spool_status(sinks),
+            SpoolAction::Flush => spool_flush(db, sinks, p).await,
+        },
+    }
+}
+
+/// Apply the pending migrations with `auto_migrate`, otherwise warn about an outdated schema
+/// (unless `migrating` with the `db` command)
+async fn prepare_storage(
+    db: &dyn Storage,
+    migrating: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if db.auto_migrate() {
+        migrate(db).await?;
+    } else if !migrating {
+        let version = db.schema_version().await?;
+        if version < db.latest_version() {
+            warn!(
+                "{}: schema version {} is outdated (current: {}), run `bluescale db migrate`",
+                db.name(),
+                version,
+                db.latest_version()
+            );
         }
-        None => Err("missing [profile] config section")?,
     }
+
+    Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    logging_init(args.debug);
+    let conf = Ini::load_from_file(&args.config);
+    // keep the standard output clean for the exported data and the JSON Lines
+    let mode = match (&args.command, &conf) {
+        (Some(Command::Export { output: None, .. }), _) => TerminalMode::Stderr,
+        (_, Ok(conf)) if config_json_lines_stdout(conf) => TerminalMode::Stderr,
+        _ => TerminalMode::Mixed,
+    };
+    logging_init(args.debug, mode);
     info!("<b><blue>bluescale</> started");
     info!("Using config file: <b><blue>{:?}</>", args.config);
-    let conf = match Ini::load_from_file(args.config) {
+    let conf = match conf {
         Ok(c) => c,
         Err(e) => {
             error!("Cannot open config file: {}", e);
@@ -112,21 +720,82 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let mut db = match config_read_postgres(conf.clone()) {
-        Ok(db) => db,
+    let db: Rc<dyn Storage> = match config_read_storage(conf.clone()) {
+        Ok(db) => Rc::from(db),
         Err(e) => {
-            return Err(format!("Config error [postgres]: {}", e).into());
+            return Err(format!("Config error {}", e).into());
         }
     };
-    let p = match config_read_profile(conf.clone()) {
+    let mut p = match config_read_profile(conf.clone()) {
         Ok(p) => {
             info!("👤 Using profile: {}", p);
+            debug!("Current age: {}", p.age_at(Utc::now().date_naive()));
             p
         }
         Err(e) => {
             return Err(format!("Config error [profile]: {}", e).into());
         }
     };
+    p.uncertainty = match config_read_uncertainty(conf.clone()) {
+        Ok(u) => u,
+        Err(e) => {
+            return Err(format!("Config error [uncertainty]: {}", e).into());
+        }
+    };
+
+    let spool = match config_read_spool(conf.clone()) {
+        Ok(spool) => spool,
+        Err(e) => {
+            return Err(format!("Config error [spool]: {}", e).into());
+        }
+    };
+    let sinks = match config_read_sinks(conf.clone(), db.clone(), &spool) {
+        Ok(sinks) => sinks,
+        Err(e) => {
+            return Err(format!("Config error {}", e).into());
+        }
+    };
+    // the spool directories are only created for the daemon and the `spool` commands
+    if matches!(args.command, None | Some(Command::Spool { .. })) {
+        for s in &sinks {
+            if let Err(e) = s.spool.open() {
+                return Err(format!("Config error [spool]: {}", e).into());
+            }
+        }
+    }
+    // the spool can be inspected while the database is unreachable
+    if let Some(Command::Spool {
+        action: SpoolAction::Status,
+    }) = args.command
+    {
+        return spool_status(&sinks);
+    }
+
+    let mut db_ready = match db.check().await {
+        Ok(()) => true,
+        // the daemon spools the measurements until the database is reachable,
+        // but the config and the credentials have to be right
+        Err(e) if args.command.is_none() && db.is_unreachable(e.as_ref()) => {
+            warn!(
+                "{}: cannot connect to the database: {}, the measurements are spooled until it is reachable",
+                db.name(),
+                e
+            );
+            false
+        }
+        Err(e) => {
+            return Err(format!("{}: cannot connect to the database: {}", db.name(), e).into());
+        }
+    };
+    if db_ready {
+        info!("{}: connected to the database", db.name());
+        let migrating = matches!(args.command, Some(Command::Db { .. }));
+        prepare_storage(db.as_ref(), migrating).await?;
+    }
+
+    if let Some(command) = args.command {
+        return run_command(command, db.as_ref(), &sinks, &p).await;
+    }
 
     let mut filter_addr: HashSet<bluer::Address> = HashSet::new();
     let mac = conf
@@ -154,12 +823,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let device_events = adapter.discover_devices().await?;
     pin_mut!(device_events);
+    //the reconnection is retried with an exponential backoff, whether or not
+    //a weigh-in arrives in the meantime
+    let mut backoff = RECONNECT_BACKOFF;
+    let spool_retry = sleep(if db_ready {
+        time::Duration::ZERO
+    } else {
+        backoff
+    });
+    pin_mut!(spool_retry);
 
     loop {
         tokio::select! {
-            Some(device_event) = device_events.next() => {
+            _ = &mut spool_retry => {
+                let mut failed = false;
+                if !db_ready {
+                    match db.check().await {
+                        Ok(()) => {
+                            info!("{}: connected to the database", db.name());
+                            match prepare_storage(db.as_ref(), false).await {
+                                Ok(()) => db_ready = true,
+                                Err(e) => error!("{}: {}", db.name(), e),
+                            }
+                        }
+                        Err(e) => {
+                            warn!("{}: cannot connect to the database: {}", db.name(), e);
+                            failed = true;
+                        }
+                    }
+                }
+                match spool_pending(&sinks) {
+                    Ok(0) => (),
+                    Ok(pending) => {
+                        info!("📤 {} measurements waiting in the spool", pending);
+                        match flush_spool(&sinks, db.as_ref(), &p).await {
+                            Ok(delivered) => info!("📤 Delivered {} spooled measurements", delivered),
+                            Err(e) => {
+                                warn!("Cannot deliver the spooled measurements: {}", e);
+                                failed = true;
+                            }
+                        }
+                    }
+                    Err(e) => error!("Cannot read the spool: {}", e),
+                }
+                let delay = if failed {
+                    let delay = backoff;
+                    backoff = (backoff * 2).min(SPOOL_RETRY_INTERVAL);
+                    info!("📤 Retrying in {} s", delay.as_secs());
+                    delay
+                } else {
+                    backoff = RECONNECT_BACKOFF;
+                    SPOOL_RETRY_INTERVAL
+                };
+                spool_retry.as_mut().reset(Instant::now() + delay);
+            }
+            device_event = device_events.next() => {
                 match device_event {
-                    AdapterEvent::DeviceAdded(addr) => {
+                    Some(AdapterEvent::DeviceAdded(addr)) => {
                         if !filter_addr.is_empty() && !filter_addr.contains(&addr) {
                             continue;
                         }
@@ -179,7 +899,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         sleep(std::time::Duration::from_secs(10)).await;
 
                         for i in 1..=10 {
-                            if let Err(e) = query_device(&adapter, addr, &mut db, p.clone()).await {
+                            if let Err(e) = query_device(&adapter, addr, db.as_ref(), &sinks, p.clone()).await {
                                 warn!("Device query error (try: {}/10): {}", i, e);
                                 thread::sleep(time::Duration::from_millis(1500));
                                 continue;
@@ -195,16 +915,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             }
                         };
                     }
-                    AdapterEvent::DeviceRemoved(addr) => {
+                    Some(AdapterEvent::DeviceRemoved(addr)) => {
                         if !filter_addr.is_empty() && !filter_addr.contains(&addr) {
                             continue;
                         }
                         info!("💤 Device removed: {}", addr);
                     }
-                    _ => (),
+                    Some(_) => (),
+                    None => break,
                 }
             }
-            else => break
         }
     }
 
@@ -0,0 +1,88 @@
+/// Versioned change of the database schema
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// PostgreSQL schema migrations in the order of their versions, applied once each
+/// and recorded in the `schema_version` table.
+///
+/// The statements are idempotent, so a hand-made `mifit` table of an older
+/// setup is upgraded in place. New migrations are only ever appended.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "measurements table",
+        sql: include_str!("../migrations/0001_mifit.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "algorithm sets",
+        sql: include_str!("../migrations/0002_algorithm.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "raw scale inputs and profile",
+        sql: include_str!("../migrations/0003_raw_inputs.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "trend columns",
+        sql: include_str!("../migrations/0004_trend.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "goal progress",
+        sql: include_str!("../migrations/0005_goal_progress.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "quarantine of the outliers",
+        sql: include_str!("../migrations/0006_quarantine.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "weekly energy balance",
+        sql: include_str!("../migrations/0007_energy_weekly.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "calibration",
+        sql: include_str!("../migrations/0008_calibration.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "unique time and profile",
+        sql: include_str!("../migrations/0009_unique_time_profile.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "skeletal muscle mass",
+        sql: include_str!("../migrations/0010_skeletal_muscle.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "unique goal progress",
+        sql: include_str!("../migrations/0011_goal_progress_unique.sql"),
+    },
+];
+
+/// Schema migrations of the SQLite storage
+pub static SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("../migrations/sqlite/0001_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "skeletal muscle mass",
+        sql: include_str!("../migrations/sqlite/0002_skeletal_muscle.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "unique goal progress",
+        sql: include_str!("../migrations/sqlite/0003_goal_progress_unique.sql"),
+    },
+];
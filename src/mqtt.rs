@@ -0,0 +1,461 @@
+use crate::body::{safe_name, Measurement, Person};
+use crate::omh::Device;
+use crate::sink::Sink;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::json;
+use simplelog::*;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep, timeout};
+
+/// Time allowed for the broker to acknowledge the messages (including the connection),
+/// the measurement is kept in the spool otherwise
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(3);
+/// Delay before reconnecting after the connection to the broker is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Messages queued to the connection
+const QUEUE_CAPACITY: usize = 64;
+
+/// Home Assistant sensor of a value in a state topic of the profile
+struct Sensor {
+    key: &'static str,
+    name: &'static str,
+    /// `measurement` or `weight`
+    topic: &'static str,
+    /// Jinja expression of the value in the JSON payload, the whole payload without it
+    value: Option<&'static str>,
+    /// the value is null when not estimated (eg. for children), the sensor is unavailable then
+    nullable: bool,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+}
+
+/// Sensors of the measurement (the calibrated values where there are some) and the live weight
+const SENSORS: &[Sensor] = &[
+    Sensor {
+        key: "weight",
+        name: "Weight",
+        topic: "measurement",
+        value: Some("value_json.weight"),
+        nullable: false,
+        unit: Some("kg"),
+        device_class: Some("weight"),
+    },
+    Sensor {
+        key: "bmi",
+        name: "BMI",
+        topic: "measurement",
+        value: Some("value_json.bmi"),
+        nullable: false,
+        unit: Some("kg/m²"),
+        device_class: None,
+    },
+    Sensor {
+        key: "body_fat",
+        name: "Body fat",
+        topic: "measurement",
+        value: Some("value_json.calibrated.bf or value_json.bf"),
+        nullable: true,
+        unit: Some("%"),
+        device_class: None,
+    },
+    Sensor {
+        key: "body_water",
+        name: "Body water",
+        topic: "measurement",
+        value: Some("value_json.calibrated.water_rate or value_json.water_rate"),
+        nullable: true,
+        unit: Some("%"),
+        device_class: None,
+    },
+    Sensor {
+        key: "muscle_mass",
+        name: "Muscle mass",
+        topic: "measurement",
+        value: Some("value_json.calibrated.muscle_kg or value_json.muscle_kg"),
+        nullable: true,
+        unit: Some("kg"),
+        device_class: Some("weight"),
+    },
+    Sensor {
+        key: "bone_mass",
+        name: "Bone mass",
+        topic: "measurement",
+        value: Some("value_json.calibrated.bone_mass or value_json.bone_mass"),
+        nullable: true,
+        unit: Some("kg"),
+        device_class: Some("weight"),
+    },
+    Sensor {
+        key: "visceral_fat",
+        name: "Visceral fat",
+        topic: "measurement",
+        value: Some("value_json.calibrated.visceral_fat or value_json.visceral_fat"),
+        nullable: true,
+        unit: None,
+        device_class: None,
+    },
+    Sensor {
+        key: "bmr",
+        name: "Basal metabolism",
+        topic: "measurement",
+        value: Some("value_json.bmr"),
+        nullable: false,
+        unit: Some("kcal"),
+        device_class: None,
+    },
+    Sensor {
+        key: "impedance",
+        name: "Impedance",
+        topic: "measurement",
+        value: Some("value_json.impedance"),
+        nullable: false,
+        unit: Some("Ω"),
+        device_class: None,
+    },
+    Sensor {
+        key: "live_weight",
+        name: "Live weight",
+        topic: "weight",
+        value: None,
+        nullable: false,
+        unit: Some("kg"),
+        device_class: Some("weight"),
+    },
+];
+
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+}
+
+/// Profile name as used in the topics and the Home Assistant ids
+fn topic_name(p: &Person) -> String {
+    safe_name(&p.name)
+}
+
+/// Profile name as used in the Home Assistant ids
+fn node_id(p: &Person) -> String {
+    format!("bluescale_{}", topic_name(p))
+}
+
+/// Publishing progress reported by the event loop
+enum Delivery {
+    /// the message was sent with the packet id
+    Sent(u16),
+    /// the broker acknowledged the packet id
+    Acked(u16),
+}
+
+/// Long-lived connection to the broker, its event loop runs in a task
+/// (keeping the connection alive and reconnecting) until the connection is dropped
+struct Connection {
+    client: AsyncClient,
+    deliveries: mpsc::UnboundedReceiver<Delivery>,
+}
+
+impl Connection {
+    fn new(options: MqttOptions) -> Connection {
+        let broker = options.broker_address().0;
+        let (client, eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let (sender, deliveries) = mpsc::unbounded_channel();
+        tokio::spawn(run(eventloop, sender, broker));
+        Connection { client, deliveries }
+    }
+}
+
+async fn run(
+    mut eventloop: EventLoop,
+    deliveries: mpsc::UnboundedSender<Delivery>,
+    broker: String,
+) {
+    let mut failing = false;
+    while !deliveries.is_closed() {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("📡 Connected to the MQTT broker {}", broker);
+                failing = false;
+            }
+            Ok(Event::Outgoing(Outgoing::Publish(pkid))) => {
+                let _ = deliveries.send(Delivery::Sent(pkid));
+            }
+            Ok(Event::Incoming(Packet::PubAck(ack))) => {
+                let _ = deliveries.send(Delivery::Acked(ack.pkid));
+            }
+            Ok(_) => (),
+            Err(e) => {
+                if !failing {
+                    warn!("MQTT broker {}: {}", broker, e);
+                    failing = true;
+                }
+                sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// MQTT broker: the measurements are published (retained) to `<topic>/<profile>/measurement`
+/// as JSON and the live weight to `<topic>/<profile>/weight`, with the Home Assistant
+/// discovery configs of the sensors announced once for each profile
+pub struct Mqtt {
+    options: MqttOptions,
+    topic: String,
+    /// discovery prefix of Home Assistant, `None` without the discovery
+    discovery_prefix: Option<String>,
+    announced: RefCell<HashSet<String>>,
+    /// connected on the first use
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Mqtt {
+    pub fn new(options: MqttOptions, topic: String, discovery_prefix: Option<String>) -> Mqtt {
+        Mqtt {
+            options,
+            topic,
+            discovery_prefix,
+            announced: RefCell::new(HashSet::new()),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn state_topic(&self, p: &Person, name: &str) -> String {
+        format!("{}/{}/{}", self.topic, topic_name(p), name)
+    }
+
+    /// Discovery configs of the sensors of the profile, if not announced yet
+    fn discovery(&self, p: &Person) -> Vec<Message> {
+        let Some(prefix) = &self.discovery_prefix else {
+            return vec![];
+        };
+        if self.announced.borrow().contains(&p.name) {
+            return vec![];
+        }
+
+        let node_id = node_id(p);
+        let scale = Device::new(None);
+        let device = json!({
+            "identifiers": [node_id],
+            "name": format!("bluescale {}", p.name),
+            "manufacturer": scale.manufacturer,
+            "model": scale.model,
+        });
+        SENSORS
+            .iter()
+            .map(|s| {
+                let state_topic = self.state_topic(p, s.topic);
+                let mut config = json!({
+                    "name": s.name,
+                    "unique_id": format!("{}_{}", node_id, s.key),
+                    "state_topic": state_topic,
+                    "state_class": "measurement",
+                    "device": device,
+                });
+                if let Some(value) = s.value {
+                    config["value_template"] = json!(format!("{{{{ {} }}}}", value));
+                    if s.nullable {
+                        config["availability"] = json!([{
+                            "topic": state_topic,
+                            "value_template": format!(
+                                "{{{{ 'offline' if ({}) is none else 'online' }}}}",
+                                value
+                            ),
+                        }]);
+                    }
+                }
+                if let Some(unit) = s.unit {
+                    config["unit_of_measurement"] = json!(unit);
+                }
+                if let Some(device_class) = s.device_class {
+                    config["device_class"] = json!(device_class);
+                }
+                Message {
+                    topic: format!("{}/sensor/{}/{}/config", prefix, node_id, s.key),
+                    payload: config.to_string().into_bytes(),
+                    retain: true,
+                }
+            })
+            .collect()
+    }
+
+    /// Publish the messages and wait until the broker acknowledges them.
+    ///
+    /// The connection is dropped when it fails, the next messages open a new one.
+    async fn publish(&self, messages: Vec<Message>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = self.connection.lock().await;
+        let c = connection.get_or_insert_with(|| Connection::new(self.options.clone()));
+        let published = publish_acked(c, messages).await;
+        if published.is_err() {
+            *connection = None;
+        }
+
+        published.map_err(|e| format!("{}: {}", self.options.broker_address().0, e).into())
+    }
+
+    /// Publish the state messages, preceded by the discovery configs of the profile
+    async fn publish_state(
+        &self,
+        p: &Person,
+        state: Vec<Message>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut messages = self.discovery(p);
+        let announce = !messages.is_empty();
+        messages.extend(state);
+        self.publish(messages).await?;
+        if announce {
+            info!("📡 Announced the sensors of {} to Home Assistant", p.name);
+            self.announced.borrow_mut().insert(p.name.clone());
+        }
+
+        Ok(())
+    }
+}
+
+async fn publish_acked(
+    c: &mut Connection,
+    messages: Vec<Message>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // deliveries of the earlier messages
+    while c.deliveries.try_recv().is_ok() {}
+
+    let count = messages.len();
+    for m in messages {
+        c.client
+            .try_publish(m.topic, QoS::AtLeastOnce, m.retain, m.payload)?;
+    }
+
+    let mut sent = HashSet::new();
+    let mut acked = 0;
+    timeout(PUBLISH_TIMEOUT, async {
+        while acked < count {
+            match c.deliveries.recv().await {
+                Some(Delivery::Sent(pkid)) => {
+                    sent.insert(pkid);
+                }
+                Some(Delivery::Acked(pkid)) => {
+                    if sent.remove(&pkid) {
+                        acked += 1;
+                    }
+                }
+                None => return Err("connection closed"),
+            }
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|_| "timeout")??;
+
+    Ok(())
+}
+
+#[async_trait(?Send)]
+impl Sink for Mqtt {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        let topic = self.state_topic(p, "measurement");
+        self.publish_state(
+            p,
+            vec![Message {
+                payload: serde_json::to_vec(m)?,
+                topic: topic.clone(),
+                retain: true,
+            }],
+        )
+        .await?;
+        info!("📡 Published the measurement to {}", topic);
+
+        Ok(())
+    }
+
+    /// Published without waiting for the broker (at most once), the sensors are announced
+    /// with the first measurement
+    async fn live_weight(&self, weight: f32, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = self.connection.lock().await;
+        let c = connection.get_or_insert_with(|| Connection::new(self.options.clone()));
+        if let Err(e) = c.client.try_publish(
+            self.state_topic(p, "weight"),
+            QoS::AtMostOnce,
+            false,
+            weight.to_string(),
+        ) {
+            // the queue is full while the broker is unreachable
+            debug!("MQTT: live weight not sent: {}", e);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outlier::OutlierFilter;
+    use crate::uncertainty::InputUncertainty;
+    use chrono::NaiveDate;
+    use serde_json::Value;
+
+    fn person(name: &str) -> Person {
+        Person {
+            name: name.to_string(),
+            sex: 1,
+            birthday: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
+        }
+    }
+
+    fn mqtt() -> Mqtt {
+        Mqtt::new(
+            MqttOptions::new("bluescale", "localhost", 1883),
+            "bluescale".to_string(),
+            Some("homeassistant".to_string()),
+        )
+    }
+
+    #[test]
+    fn sanitized_topics() {
+        let p = person("jo+hn/#");
+        assert_eq!(
+            mqtt().state_topic(&p, "measurement"),
+            "bluescale/jo_hn__/measurement"
+        );
+        assert_eq!(node_id(&p), "bluescale_jo_hn__");
+    }
+
+    #[test]
+    fn discovery_configs() {
+        let mqtt = mqtt();
+        let configs = mqtt.discovery(&person("john"));
+        assert_eq!(configs.len(), SENSORS.len());
+
+        let config = |key: &str| -> Value {
+            let m = configs
+                .iter()
+                .find(|m| m.topic == format!("homeassistant/sensor/bluescale_john/{}/config", key))
+                .unwrap();
+            assert!(m.retain);
+            serde_json::from_slice(&m.payload).unwrap()
+        };
+        let weight = config("weight");
+        assert_eq!(weight["state_topic"], "bluescale/john/measurement");
+        assert_eq!(weight["value_template"], "{{ value_json.weight }}");
+        assert!(weight.get("availability").is_none());
+
+        let body_fat = config("body_fat");
+        assert_eq!(
+            body_fat["availability"][0]["value_template"],
+            "{{ 'offline' if (value_json.calibrated.bf or value_json.bf) is none else 'online' }}"
+        );
+        assert!(config("live_weight").get("value_template").is_none());
+    }
+}
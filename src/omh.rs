@@ -0,0 +1,263 @@
+use crate::body::{Measurement, Person};
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+
+/// Time of the data points, the measurement times are UTC
+fn format_time(date_time: NaiveDateTime) -> String {
+    date_time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// The scale producing the measurements
+#[derive(Clone, Serialize)]
+pub struct Device {
+    pub manufacturer: &'static str,
+    pub model: &'static str,
+    /// Bluetooth address of the scale, if configured
+    pub address: Option<String>,
+}
+
+impl Device {
+    pub fn new(address: Option<String>) -> Device {
+        Device {
+            manufacturer: "Xiaomi",
+            model: "Mi Body Composition Scale 2",
+            address,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SchemaId {
+    pub namespace: &'static str,
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct AcquisitionProvenance {
+    pub source_name: &'static str,
+    pub source_creation_date_time: String,
+    pub modality: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Header {
+    pub id: String,
+    pub creation_date_time: String,
+    pub schema_id: SchemaId,
+    pub acquisition_provenance: AcquisitionProvenance,
+    pub user_id: String,
+}
+
+/// Open mHealth data point: the header and the body of the schema
+#[derive(Serialize)]
+pub struct DataPoint<B> {
+    pub header: Header,
+    pub body: B,
+}
+
+#[derive(Serialize)]
+pub struct UnitValue {
+    pub value: f32,
+    pub unit: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct TimeFrame {
+    pub date_time: String,
+}
+
+/// Body of the `omh:body-weight:2.0` schema
+#[derive(Serialize)]
+pub struct BodyWeight {
+    pub body_weight: UnitValue,
+    pub effective_time_frame: TimeFrame,
+}
+
+/// Body of the `omh:body-fat-percentage:1.0` schema
+#[derive(Serialize)]
+pub struct BodyFatPercentage {
+    pub body_fat_percentage: UnitValue,
+    pub effective_time_frame: TimeFrame,
+}
+
+/// Raw inputs from the scale
+#[derive(Serialize)]
+pub struct Raw {
+    pub weight: f32,
+    pub impedance: f32,
+    pub flags: u16,
+}
+
+/// Record of a weigh-in: the Open mHealth data points (the calibrated body fat where
+/// there is one), with the profile, the scale, the raw inputs and all the computed values
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub body_weight: DataPoint<BodyWeight>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_fat_percentage: Option<DataPoint<BodyFatPercentage>>,
+    pub profile: &'a Person,
+    pub device: &'a Device,
+    pub raw: Raw,
+    pub measurement: &'a Measurement,
+}
+
+fn header(m: &Measurement, p: &Person, name: &'static str, version: &'static str) -> Header {
+    Header {
+        id: uuid::Uuid::new_v4().to_string(),
+        creation_date_time: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        schema_id: SchemaId {
+            namespace: "omh",
+            name,
+            version,
+        },
+        acquisition_provenance: AcquisitionProvenance {
+            source_name: "bluescale",
+            source_creation_date_time: format_time(m.date_time),
+            modality: "sensed",
+        },
+        user_id: p.name.clone(),
+    }
+}
+
+impl<'a> Record<'a> {
+    pub fn new(m: &'a Measurement, p: &'a Person, device: &'a Device) -> Record<'a> {
+        let time_frame = || TimeFrame {
+            date_time: format_time(m.date_time),
+        };
+        Record {
+            body_weight: DataPoint {
+                header: header(m, p, "body-weight", "2.0"),
+                body: BodyWeight {
+                    body_weight: UnitValue {
+                        value: m.weight,
+                        unit: "kg",
+                    },
+                    effective_time_frame: time_frame(),
+                },
+            },
+            body_fat_percentage: m.calibrated.bf.or(m.bf).map(|bf| DataPoint {
+                header: header(m, p, "body-fat-percentage", "1.0"),
+                body: BodyFatPercentage {
+                    body_fat_percentage: UnitValue {
+                        value: bf,
+                        unit: "%",
+                    },
+                    effective_time_frame: time_frame(),
+                },
+            }),
+            profile: p,
+            device,
+            raw: Raw {
+                weight: m.weight,
+                impedance: m.impedance,
+                flags: m.flags,
+            },
+            measurement: m,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Composition;
+    use chrono::NaiveDate;
+    use serde_json::{json, Value};
+
+    fn measurement(p: &Person, bf: Option<f32>) -> Measurement {
+        let date_time = NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        let composition = Composition {
+            bf,
+            ..Default::default()
+        };
+        p.measure_known(date_time, 80.5, &composition, "test")
+    }
+
+    /// Record as JSON with the random ids and the creation times replaced
+    fn snapshot(m: &Measurement, p: &Person) -> Value {
+        let device = Device::new(Some("AA:BB:CC:DD:EE:FF".to_string()));
+        let mut record = serde_json::to_value(Record::new(m, p, &device)).unwrap();
+        for name in ["body_weight", "body_fat_percentage"] {
+            if let Some(header) = record.get_mut(name).map(|point| &mut point["header"]) {
+                assert!(uuid::Uuid::parse_str(header["id"].as_str().unwrap()).is_ok());
+                header["id"] = json!("<uuid>");
+                header["creation_date_time"] = json!("<now>");
+            }
+        }
+        record
+    }
+
+    fn header(name: &str, version: &str) -> Value {
+        json!({
+            "id": "<uuid>",
+            "creation_date_time": "<now>",
+            "schema_id": {"namespace": "omh", "name": name, "version": version},
+            "acquisition_provenance": {
+                "source_name": "bluescale",
+                "source_creation_date_time": "2026-10-18T08:00:00Z",
+                "modality": "sensed",
+            },
+            "user_id": "test",
+        })
+    }
+
+    #[test]
+    fn data_points() {
+        let p = crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap());
+        let record = snapshot(&measurement(&p, Some(20.25)), &p);
+        assert_eq!(
+            record["body_weight"],
+            json!({
+                "header": header("body-weight", "2.0"),
+                "body": {
+                    "body_weight": {"value": 80.5, "unit": "kg"},
+                    "effective_time_frame": {"date_time": "2026-10-18T08:00:00Z"},
+                },
+            })
+        );
+        assert_eq!(
+            record["body_fat_percentage"],
+            json!({
+                "header": header("body-fat-percentage", "1.0"),
+                "body": {
+                    "body_fat_percentage": {"value": 20.25, "unit": "%"},
+                    "effective_time_frame": {"date_time": "2026-10-18T08:00:00Z"},
+                },
+            })
+        );
+        assert_eq!(
+            record["device"],
+            json!({
+                "manufacturer": "Xiaomi",
+                "model": "Mi Body Composition Scale 2",
+                "address": "AA:BB:CC:DD:EE:FF",
+            })
+        );
+        assert_eq!(
+            record["raw"],
+            json!({"weight": 80.5, "impedance": 0.0, "flags": 0})
+        );
+        assert_eq!(record["profile"]["name"], "test");
+        assert_eq!(record["measurement"]["bf"], 20.25);
+    }
+
+    #[test]
+    fn calibrated_or_missing_body_fat() {
+        let p = crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap());
+        let mut m = measurement(&p, Some(20.25));
+        m.calibrated.bf = Some(22.5);
+        let record = snapshot(&m, &p);
+        assert_eq!(
+            record["body_fat_percentage"]["body"]["body_fat_percentage"],
+            json!({"value": 22.5, "unit": "%"})
+        );
+
+        let record = snapshot(&measurement(&p, None), &p);
+        assert!(record.get("body_fat_percentage").is_none());
+        assert!(record.get("body_weight").is_some());
+    }
+}
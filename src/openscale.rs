@@ -0,0 +1,244 @@
+use crate::body::{Composition, Measurement};
+use crate::import::{parse_time, Imported};
+use crate::storage::StoredReading;
+use chrono::{Duration, Local, NaiveDateTime, Offset, TimeZone};
+use std::io::{Read, Write};
+
+/// Time format of the openScale backup (local time, to the minute)
+const TIME_FORMAT: &str = "%d.%m.%Y %H:%M";
+
+/// Columns of the openScale backup used by bluescale, the others (body measures) are left empty
+const COLUMNS: &[&str] = &[
+    "dateTime",
+    "weight",
+    "fat",
+    "water",
+    "muscle",
+    "lbm",
+    "bone",
+    "visceralFat",
+    "calories",
+    "comment",
+];
+
+/// Time of the backup in the time zone as UTC
+fn parse_openscale_time<Tz: TimeZone>(
+    value: &str,
+    tz: &Tz,
+) -> Result<NaiveDateTime, Box<dyn std::error::Error>> {
+    let local =
+        NaiveDateTime::parse_from_str(value.trim(), TIME_FORMAT).or_else(|_| parse_time(value))?;
+    Ok(match tz.from_local_datetime(&local).earliest() {
+        Some(time) => time.naive_utc(),
+        // skipped by the change to the daylight saving time, with the offset after it
+        None => {
+            let offset = tz.offset_from_utc_datetime(&local).fix();
+            local - Duration::seconds(offset.local_minus_utc().into())
+        }
+    })
+}
+
+/// Read the CSV backup of [openScale](https://github.com/oliexdev/openScale):
+/// weight (kg), fat, water and muscle (%), lbm and bone (kg), visceralFat and calories (kcal).
+///
+/// openScale has no impedance, so the values are stored as they are (0 is unknown).
+/// The lean body mass is the `lbm`, or computed from the `muscle` percentage.
+/// The times are local.
+pub fn read_csv<R: Read>(reader: R) -> Result<Vec<Imported>, Box<dyn std::error::Error>> {
+    read_csv_in(reader, &Local)
+}
+
+fn read_csv_in<R: Read, Tz: TimeZone>(
+    reader: R,
+    tz: &Tz,
+) -> Result<Vec<Imported>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let index = |name: &str| headers.iter().position(|h| h == name);
+    let time = index("dateTime").ok_or("missing column `dateTime`")?;
+    let weight = index("weight").ok_or("missing column `weight`")?;
+    let [fat, water, muscle, lbm, bone, visceral_fat, calories] = [
+        "fat",
+        "water",
+        "muscle",
+        "lbm",
+        "bone",
+        "visceralFat",
+        "calories",
+    ]
+    .map(index);
+
+    let mut imported = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let value = |i: Option<usize>| -> Result<Option<f32>, Box<dyn std::error::Error>> {
+            match i.and_then(|i| record.get(i)) {
+                Some(v) if !v.is_empty() => Ok(Some(v.parse::<f32>()?).filter(|v| *v != 0.0)),
+                _ => Ok(None),
+            }
+        };
+        let parse = || -> Result<Imported, Box<dyn std::error::Error>> {
+            let weight = value(Some(weight))?.ok_or("missing weight")?;
+            Ok(Imported {
+                profile: None,
+                reading: StoredReading {
+                    date_time: parse_openscale_time(record.get(time).unwrap_or_default(), tz)?,
+                    weight,
+                    impedance: 0.0,
+                    flags: 0,
+                },
+                composition: Composition {
+                    water_rate: value(water)?,
+                    bmr: value(calories)?,
+                    visceral_fat: value(visceral_fat)?,
+                    bf: value(fat)?,
+                    muscle_kg: value(lbm)?.or(value(muscle)?.map(|rate| rate * weight / 100.0)),
+                    bone_mass: value(bone)?,
+                },
+            })
+        };
+        imported.push(parse().map_err(|e| format!("line {}: {}", line + 2, e))?);
+    }
+
+    Ok(imported)
+}
+
+/// Write the measurements as an openScale CSV backup (in local time), which can be restored in the app
+pub fn write_csv<W: Write>(
+    writer: W,
+    measurements: &[Measurement],
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_csv_in(writer, measurements, &Local)
+}
+
+fn write_csv_in<W: Write, Tz: TimeZone>(
+    writer: W,
+    measurements: &[Measurement],
+    tz: &Tz,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(COLUMNS)?;
+
+    // openScale stores the unknown values as 0
+    let num = |v: Option<f32>| format!("{:.2}", v.unwrap_or_default());
+    for m in measurements {
+        writer.write_record(&[
+            tz.from_utc_datetime(&m.date_time)
+                .format(TIME_FORMAT)
+                .to_string(),
+            num(Some(m.weight)),
+            num(m.bf),
+            num(m.water_rate),
+            num(m.muscle_rate),
+            num(m.muscle_kg),
+            num(m.bone_mass),
+            num(m.visceral_fat),
+            num(Some(m.bmr)),
+            String::new(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Person;
+    use crate::outlier::OutlierFilter;
+    use crate::uncertainty::InputUncertainty;
+    use chrono::{FixedOffset, NaiveDate};
+
+    const BACKUP: &str = "\
+\"dateTime\",\"weight\",\"fat\",\"water\",\"muscle\",\"lbm\",\"bone\",\"visceralFat\",\"waist\",\"calories\",\"comment\"
+\"05.01.2023 08:30\",\"80.0\",\"20.0\",\"55.0\",\"40.0\",\"0.0\",\"3.2\",\"9.0\",\"0.0\",\"1750.0\",\"\"
+\"2023-01-06 07:15\",\"79.5\",\"0.0\",\"\",\"\",\"60.0\",\"\",\"\",\"\",\"\",\"\"
+";
+
+    fn time(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn read_backup() {
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let imported = read_csv_in(BACKUP.as_bytes(), &cet).unwrap();
+        assert_eq!(imported.len(), 2);
+
+        let (r, c) = (&imported[0].reading, &imported[0].composition);
+        assert_eq!(r.date_time, time(2023, 1, 5, 7, 30));
+        assert_eq!(r.weight, 80.0);
+        assert_eq!(r.impedance, 0.0);
+        assert_eq!(c.bf, Some(20.0));
+        assert_eq!(c.water_rate, Some(55.0));
+        // no `lbm`, computed from the `muscle` percentage
+        assert_eq!(c.muscle_kg, Some(32.0));
+        assert_eq!(c.bone_mass, Some(3.2));
+        assert_eq!(c.visceral_fat, Some(9.0));
+        assert_eq!(c.bmr, Some(1750.0));
+
+        let (r, c) = (&imported[1].reading, &imported[1].composition);
+        assert_eq!(r.date_time, time(2023, 1, 6, 6, 15));
+        assert_eq!(c.bf, None);
+        assert_eq!(c.water_rate, None);
+        assert_eq!(c.muscle_kg, Some(60.0));
+    }
+
+    fn read_error(backup: &str) -> String {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        match read_csv_in(backup.as_bytes(), &utc) {
+            Ok(_) => panic!("read without an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        assert!(read_error("\"weight\"\n\"80.0\"\n").contains("`dateTime`"));
+        let invalid = "dateTime,weight\n05.01.2023 08:30,80\n31.02.2023 08:30,80\n";
+        assert!(read_error(invalid).starts_with("line 3:"));
+        assert!(read_error("dateTime,weight\n05.01.2023 08:30,0\n").contains("missing weight"));
+    }
+
+    #[test]
+    fn write_local_time() {
+        let p = Person {
+            name: "test".to_string(),
+            sex: 1,
+            birthday: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
+        };
+        let m = p.measure_known(
+            time(2023, 1, 5, 23, 30),
+            80.0,
+            &Composition::default(),
+            "openscale",
+        );
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let mut out = vec![];
+        write_csv_in(&mut out, &[m], &cet).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("06.01.2023 00:30,80.00,"));
+
+        let imported = read_csv_in(out.as_bytes(), &cet).unwrap();
+        assert_eq!(imported[0].reading.date_time, time(2023, 1, 5, 23, 30));
+    }
+}
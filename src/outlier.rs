@@ -0,0 +1,162 @@
+use crate::body::Measurement;
+use crate::trend::Sample;
+use serde::Serialize;
+
+/// Limits of physiologically plausible changes compared to the recent history
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct OutlierFilter {
+    /// allowed weight deviation from the recent median weight (kg)
+    pub max_weight_change: f32,
+    /// additional allowed weight deviation per day since the last measurement (kg)
+    pub max_weight_change_per_day: f32,
+    /// allowed impedance deviation from the recent median impedance (%)
+    pub max_impedance_change: f32,
+}
+
+impl Default for OutlierFilter {
+    fn default() -> Self {
+        OutlierFilter {
+            max_weight_change: 3.0,
+            max_weight_change_per_day: 0.3,
+            max_impedance_change: 30.0,
+        }
+    }
+}
+
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+impl OutlierFilter {
+    /// Check the measurement against the recent history (ordered by time).
+    ///
+    /// Returns the reason if the measurement is implausible for this person
+    /// (eg. a guest or a child stepped on the scale).
+    pub fn check(&self, history: &[Sample], m: &Measurement) -> Option<String> {
+        let last = history.last()?;
+
+        let days = (m.date_time - last.date_time).num_seconds().max(0) as f32 / 86400.0;
+        let allowed = self.max_weight_change + self.max_weight_change_per_day * days;
+        if let Some(weight) = median(history.iter().map(|s| s.weight).collect()) {
+            if (m.weight - weight).abs() > allowed {
+                return Some(format!(
+                    "weight {:.2} kg differs from the recent {:.2} kg by more than {:.2} kg",
+                    m.weight, weight, allowed
+                ));
+            }
+        }
+
+        if let Some(impedance) = median(history.iter().filter_map(|s| s.impedance).collect()) {
+            let change = (m.impedance - impedance).abs() / impedance * 100.0;
+            if change > self.max_impedance_change {
+                return Some(format!(
+                    "impedance {} Ω differs from the recent {} Ω by {:.0} %",
+                    m.impedance, impedance, change
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Person;
+    use crate::uncertainty::InputUncertainty;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn at(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 1, day)
+            .unwrap()
+            .and_hms_opt(7, 0, 0)
+            .unwrap()
+    }
+
+    fn sample(day: u32, weight: f32, impedance: Option<f32>) -> Sample {
+        Sample {
+            date_time: at(day),
+            weight,
+            fat: None,
+            muscle: None,
+            impedance,
+            bmr: Some(1700.0),
+            trend_weight: None,
+        }
+    }
+
+    fn measurement(day: u32, weight: f32, impedance: f32) -> Measurement {
+        let p = Person {
+            name: "test".to_string(),
+            sex: 1,
+            birthday: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty: InputUncertainty::default(),
+        };
+        p.measure(at(day), weight, impedance, 0)
+    }
+
+    #[test]
+    fn empty_history_accepts_anything() {
+        let filter = OutlierFilter::default();
+        assert_eq!(filter.check(&[], &measurement(1, 30.0, 900.0)), None);
+    }
+
+    #[test]
+    fn weight_threshold() {
+        let filter = OutlierFilter::default();
+        let history = [sample(1, 80.0, None), sample(2, 80.0, None)];
+        // 3 kg plus 0.3 kg for the day since the last measurement
+        assert_eq!(filter.check(&history, &measurement(3, 83.2, 500.0)), None);
+        assert_eq!(filter.check(&history, &measurement(3, 76.8, 500.0)), None);
+        let reason = filter
+            .check(&history, &measurement(3, 83.5, 500.0))
+            .unwrap();
+        assert!(reason.starts_with("weight 83.50 kg"), "{}", reason);
+        // the allowed change grows with the days since the last measurement
+        assert_eq!(filter.check(&history, &measurement(12, 85.5, 500.0)), None);
+    }
+
+    #[test]
+    fn weight_compared_with_median() {
+        let filter = OutlierFilter::default();
+        // a single guest weigh-in in the history doesn't move the reference
+        let history = [
+            sample(1, 80.0, None),
+            sample(2, 30.0, None),
+            sample(2, 80.4, None),
+        ];
+        assert_eq!(filter.check(&history, &measurement(2, 81.0, 500.0)), None);
+        assert!(filter
+            .check(&history, &measurement(2, 30.0, 500.0))
+            .is_some());
+    }
+
+    #[test]
+    fn impedance_threshold() {
+        let filter = OutlierFilter::default();
+        let history = [sample(1, 80.0, Some(500.0)), sample(2, 80.0, Some(500.0))];
+        assert_eq!(filter.check(&history, &measurement(2, 80.0, 640.0)), None);
+        let reason = filter
+            .check(&history, &measurement(2, 80.0, 660.0))
+            .unwrap();
+        assert!(reason.starts_with("impedance 660 Ω"), "{}", reason);
+        // no stored impedance to compare with
+        let history = [sample(1, 80.0, None)];
+        assert_eq!(filter.check(&history, &measurement(1, 80.0, 900.0)), None);
+    }
+}
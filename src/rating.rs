@@ -0,0 +1,196 @@
+use serde::Serialize;
+use std::fmt;
+use strum::Display;
+
+/// Age range (years) in which the adult body composition formulas were validated
+pub const ADULT_AGE: f32 = 18.0;
+pub const VALIDATED_MAX_AGE: f32 = 80.0;
+/// Older adults get the BMI rating ranges of the elderly
+pub const SENIOR_AGE: f32 = 65.0;
+
+/// Rating of a value against the reference ranges for the age and sex
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, Serialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Rating {
+    Underweight,
+    Healthy,
+    Overweight,
+    Obese,
+}
+
+/// Rate the adult BMI: WHO ranges, for older adults the ranges of Lipschitz (1994)
+pub fn rate_bmi(bmi: f32, age: f32) -> Rating {
+    let (low, high, obese) = if age >= SENIOR_AGE {
+        (22.0, 27.0, 30.0)
+    } else {
+        (18.5, 25.0, 30.0)
+    };
+    rate(bmi, low, high, obese)
+}
+
+/// Rate the adult body fat (%): ranges of Gallagher et al. (2000) by sex and age group
+pub fn rate_body_fat(bf: f32, sex: u8, age: f32) -> Rating {
+    let (low, high, obese) = match (sex, age) {
+        (0, a) if a < 40.0 => (21.0, 33.0, 39.0),
+        (0, a) if a < 60.0 => (23.0, 34.0, 40.0),
+        (0, _) => (24.0, 36.0, 42.0),
+        (_, a) if a < 40.0 => (8.0, 20.0, 25.0),
+        (_, a) if a < 60.0 => (11.0, 22.0, 28.0),
+        (_, _) => (13.0, 25.0, 30.0),
+    };
+    rate(bf, low, high, obese)
+}
+
+fn rate(value: f32, low: f32, high: f32, obese: f32) -> Rating {
+    if value < low {
+        Rating::Underweight
+    } else if value < high {
+        Rating::Healthy
+    } else if value < obese {
+        Rating::Overweight
+    } else {
+        Rating::Obese
+    }
+}
+
+/// BMI-for-age of children and adolescents (2-20 years)
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BmiForAge {
+    pub z_score: f32,
+    pub percentile: f32,
+    pub rating: Rating,
+}
+
+impl fmt::Display for BmiForAge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}. percentile (z-score {:+.2}, {})",
+            self.percentile, self.z_score, self.rating
+        )
+    }
+}
+
+/// LMS parameters (L, M, S) of the BMI-for-age at whole years 2..=20,
+/// approximated from the CDC 2000 growth charts
+const LMS_MALE: [(f32, f32, f32); 19] = [
+    (-2.01, 16.57, 0.0806),
+    (-1.70, 16.00, 0.0768),
+    (-1.60, 15.66, 0.0768),
+    (-1.75, 15.45, 0.0800),
+    (-2.05, 15.40, 0.0870),
+    (-2.35, 15.50, 0.0960),
+    (-2.60, 15.78, 0.1060),
+    (-2.75, 16.15, 0.1150),
+    (-2.80, 16.60, 0.1230),
+    (-2.80, 17.18, 0.1280),
+    (-2.70, 17.82, 0.1310),
+    (-2.55, 18.50, 0.1320),
+    (-2.35, 19.18, 0.1310),
+    (-2.15, 19.84, 0.1290),
+    (-1.95, 20.48, 0.1270),
+    (-1.75, 21.10, 0.1250),
+    (-1.55, 21.68, 0.1240),
+    (-1.35, 22.20, 0.1240),
+    (-1.15, 22.63, 0.1250),
+];
+const LMS_FEMALE: [(f32, f32, f32); 19] = [
+    (-0.98, 16.42, 0.0809),
+    (-1.25, 15.80, 0.0820),
+    (-1.55, 15.46, 0.0860),
+    (-1.85, 15.27, 0.0920),
+    (-2.10, 15.27, 0.1000),
+    (-2.25, 15.46, 0.1090),
+    (-2.30, 15.82, 0.1180),
+    (-2.30, 16.30, 0.1260),
+    (-2.25, 16.86, 0.1330),
+    (-2.15, 17.48, 0.1380),
+    (-2.05, 18.12, 0.1410),
+    (-1.95, 18.74, 0.1430),
+    (-1.85, 19.33, 0.1430),
+    (-1.75, 19.87, 0.1430),
+    (-1.65, 20.36, 0.1430),
+    (-1.55, 20.81, 0.1440),
+    (-1.45, 21.20, 0.1450),
+    (-1.35, 21.52, 0.1460),
+    (-1.25, 21.71, 0.1470),
+];
+
+/// Standard normal cumulative distribution (Abramowitz & Stegun 26.2.17)
+#[allow(clippy::excessive_precision)]
+fn normal_cdf(z: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.2316419 * z.abs());
+    let poly = t
+        * (0.319381530
+            + t * (-0.356563782 + t * (1.781477937 + t * (-1.821255978 + t * 1.330274429))));
+    let p = 1.0 - (-z * z / 2.0).exp() / (2.0 * std::f32::consts::PI).sqrt() * poly;
+    if z >= 0.0 {
+        p
+    } else {
+        1.0 - p
+    }
+}
+
+/// BMI-for-age percentile, `None` outside of the 2-20 years range
+pub fn bmi_for_age(bmi: f32, sex: u8, age: f32) -> Option<BmiForAge> {
+    if !(2.0..=20.0).contains(&age) {
+        return None;
+    }
+    let table = if sex == 0 { &LMS_FEMALE } else { &LMS_MALE };
+    let pos = age - 2.0;
+    let i = (pos.floor() as usize).min(table.len() - 2);
+    let frac = pos - i as f32;
+    let lerp = |a: f32, b: f32| a + (b - a) * frac;
+    let (l, m, s) = (
+        lerp(table[i].0, table[i + 1].0),
+        lerp(table[i].1, table[i + 1].1),
+        lerp(table[i].2, table[i + 1].2),
+    );
+
+    let z_score = ((bmi / m).powf(l) - 1.0) / (l * s);
+    let percentile = normal_cdf(z_score) * 100.0;
+    let rating = if percentile < 5.0 {
+        Rating::Underweight
+    } else if percentile < 85.0 {
+        Rating::Healthy
+    } else if percentile < 95.0 {
+        Rating::Overweight
+    } else {
+        Rating::Obese
+    };
+
+    Some(BmiForAge {
+        z_score,
+        percentile,
+        rating,
+    })
+}
+
+/// Basal metabolism of children and adolescents (kcal), Schofield (1985) weight equations
+pub fn schofield_bmr(weight: f32, sex: u8, age: f32) -> f32 {
+    match (sex, age) {
+        (0, a) if a < 3.0 => 58.317 * weight - 31.1,
+        (0, a) if a < 10.0 => 20.315 * weight + 485.9,
+        (0, _) => 13.384 * weight + 692.6,
+        (_, a) if a < 3.0 => 59.512 * weight - 30.4,
+        (_, a) if a < 10.0 => 22.706 * weight + 504.3,
+        (_, _) => 17.686 * weight + 658.2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schofield_age_bands() {
+        let bmr = |weight, sex, age| schofield_bmr(weight, sex, age).round();
+        assert_eq!(bmr(12.0, 0, 2.0), 669.0);
+        assert_eq!(bmr(20.0, 0, 6.0), 892.0);
+        assert_eq!(bmr(45.0, 0, 14.0), 1295.0);
+        assert_eq!(bmr(12.0, 1, 2.0), 684.0);
+        assert_eq!(bmr(20.0, 1, 6.0), 958.0);
+        assert_eq!(bmr(45.0, 1, 14.0), 1454.0);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::body::{Measurement, Person};
+use crate::energy::{week_start, EnergyBalance};
+use crate::fit;
+use crate::goal::Progress;
+use crate::omh::{Device, Record};
+use crate::spool::Spool;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use simplelog::*;
+use std::cell::Cell;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// Destination of the measurements, eg. the database or a log file
+///
+/// Every sink is delivered to independently: a measurement it cannot take
+/// is kept in its own spool and retried later.
+#[async_trait(?Send)]
+pub trait Sink {
+    /// Name of the sink, also the name of its spool subdirectory
+    fn name(&self) -> &str;
+
+    /// Deliver the measurement
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Deliver the measurement rejected as an outlier, ignored by default
+    async fn quarantine(
+        &self,
+        _m: &Measurement,
+        _p: &Person,
+        _reason: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Show the weight while the impedance is being measured, ignored by default.
+    /// It is not spooled.
+    async fn live_weight(
+        &self,
+        _weight: f32,
+        _p: &Person,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+/// Sink with the spool of the measurements not yet delivered to it
+pub struct SinkSpool {
+    pub sink: Box<dyn Sink>,
+    pub spool: Spool,
+}
+
+/// The configured storage: the measurement with the goal progress and the weekly energy balance
+pub struct StorageSink {
+    db: Rc<dyn Storage>,
+    /// the schema was found up to date
+    schema_current: Cell<bool>,
+}
+
+impl StorageSink {
+    pub fn new(db: Rc<dyn Storage>) -> StorageSink {
+        StorageSink {
+            db,
+            schema_current: Cell::new(false),
+        }
+    }
+
+    /// Refuse to store into an outdated schema (eg. a table created by hand without
+    /// the newer columns), the measurement is kept in the spool until it is migrated
+    async fn check_schema(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.schema_current.get() {
+            return Ok(());
+        }
+        let version = self.db.schema_version().await?;
+        if version < self.db.latest_version() {
+            return Err(format!(
+                "schema version {} is outdated (current: {}), run `bluescale db migrate`",
+                version,
+                self.db.latest_version()
+            )
+            .into());
+        }
+        self.schema_current.set(true);
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Sink for StorageSink {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        info!("🛢️  Storing measurement in the database");
+        self.check_schema().await?;
+        self.db.insert_measurement(m, p, None).await?;
+
+        // energy balance of the current week, including this measurement
+        let week = week_start(m.date_time.date());
+        match self
+            .db
+            .fetch_samples(&p.name, week.and_hms_opt(0, 0, 0))
+            .await
+        {
+            Ok(samples) => {
+                if let Some(energy) = EnergyBalance::estimate(&samples, p.activity) {
+                    info!("🔥 {}", energy);
+                    if let Err(e) = self
+                        .db
+                        .upsert_energy_weekly(&p.name, &[(week, energy)])
+                        .await
+                    {
+                        warn!("Cannot store the weekly energy balance: {}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Cannot compute the weekly energy balance: {}", e),
+        }
+
+        let progress: Vec<Progress> = match m.trend {
+            Some(trend) => p
+                .goals
+                .iter()
+                .filter_map(|g| g.progress(&m.sample(), &trend))
+                .collect(),
+            None => vec![],
+        };
+        for pr in &progress {
+            info!("🎯 {}", pr);
+        }
+        if !progress.is_empty() {
+            if let Err(e) = self.db.insert_progress(&p.name, &progress).await {
+                warn!("Cannot store the goal progress: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn quarantine(
+        &self,
+        m: &Measurement,
+        p: &Person,
+        reason: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!("🛢️  Storing measurement in the quarantine");
+        self.check_schema().await?;
+        self.db.insert_measurement(m, p, Some(reason)).await
+    }
+}
+
+/// Columns of the CSV log
+const CSV_LOG_HEADER: &[&str] = &[
+    "time",
+    "profile",
+    "weight",
+    "impedance",
+    "bmi",
+    "fat_rate",
+    "body_water_rate",
+    "muscle_kg",
+    "bone_mass",
+    "visceral_fat",
+    "metabolism",
+    "weight_trend",
+];
+
+/// CSV file with a line appended for each measurement
+pub struct CsvLog {
+    pub path: PathBuf,
+}
+
+#[async_trait(?Send)]
+impl Sink for CsvLog {
+    fn name(&self) -> &str {
+        "csv_log"
+    }
+
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut writer = csv::Writer::from_writer(&file);
+        if empty {
+            writer.write_record(CSV_LOG_HEADER)?;
+        }
+        let opt = |v: Option<f32>| v.map(|v| v.to_string()).unwrap_or_default();
+        writer.write_record(&[
+            m.date_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            p.name.clone(),
+            m.weight.to_string(),
+            m.impedance.to_string(),
+            m.bmi.to_string(),
+            opt(m.bf),
+            opt(m.water_rate),
+            opt(m.muscle_kg),
+            opt(m.bone_mass),
+            opt(m.visceral_fat),
+            m.bmr.to_string(),
+            opt(m.trend.map(|t| t.weight)),
+        ])?;
+        writer.flush()?;
+        drop(writer);
+        file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// Directory with a Garmin FIT weight file written for each measurement
+pub struct FitDir {
+    pub path: PathBuf,
+}
+
+#[async_trait(?Send)]
+impl Sink for FitDir {
+    fn name(&self) -> &str {
+        "garmin_fit"
+    }
+
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        let path = fit::write_dir(&self.path, p, std::slice::from_ref(m))?;
+        info!("⌚ Written FIT file: {}", path.display());
+
+        Ok(())
+    }
+}
+
+/// JSON Lines file (or the standard output) with an Open mHealth record written for each measurement
+pub struct JsonLines {
+    /// `None` is the standard output
+    pub path: Option<PathBuf>,
+    pub device: Device,
+}
+
+#[async_trait(?Send)]
+impl Sink for JsonLines {
+    fn name(&self) -> &str {
+        "json_lines"
+    }
+
+    async fn send(&self, m: &Measurement, p: &Person) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_vec(&Record::new(m, p, &self.device))?;
+        line.push(b'\n');
+        match &self.path {
+            Some(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(&line)?;
+                file.sync_data()?;
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(&line)?;
+                stdout.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sqlite::Sqlite;
+    use chrono::{NaiveDate, NaiveDateTime};
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+    use std::path::Path;
+
+    /// Sink failing on request, with the times of the measurements delivered to it
+    #[derive(Clone, Default)]
+    pub(crate) struct FakeSink {
+        pub failing: Rc<Cell<bool>>,
+        pub sent: Rc<RefCell<Vec<NaiveDateTime>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Sink for FakeSink {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        async fn send(
+            &self,
+            m: &Measurement,
+            _p: &Person,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            if self.failing.get() {
+                return Err("unreachable".into());
+            }
+            self.sent.borrow_mut().push(m.date_time);
+            Ok(())
+        }
+    }
+
+    fn person() -> Person {
+        crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap())
+    }
+
+    #[test]
+    fn storage_sink_refuses_outdated_schema() {
+        let db = Rc::new(Sqlite::open("test", Path::new(":memory:")).unwrap());
+        let sink = StorageSink::new(db.clone());
+        let p = person();
+        let m = p.measure(
+            NaiveDate::from_ymd_opt(2026, 10, 18)
+                .unwrap()
+                .and_hms_opt(8, 0, 0)
+                .unwrap(),
+            80.0,
+            500.0,
+            0,
+        );
+
+        let e = block_on(sink.send(&m, &p)).unwrap_err();
+        assert!(e.to_string().contains("run `bluescale db migrate`"));
+
+        block_on(db.migrate()).unwrap();
+        block_on(sink.send(&m, &p)).unwrap();
+        assert_eq!(block_on(db.fetch_samples("test", None)).unwrap().len(), 1);
+    }
+}
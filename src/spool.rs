@@ -0,0 +1,186 @@
+use crate::body::safe_name;
+use crate::storage::StoredReading;
+use chrono::NaiveDateTime;
+use ini::Ini;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// On-disk spool of the measurements not yet delivered to a sink
+///
+/// Each measurement is kept as a small ini file with the raw scale inputs,
+/// so it can be recomputed and delivered once the sink is reachable.
+#[derive(Clone)]
+pub struct Spool {
+    pub dir: PathBuf,
+}
+
+/// Measurement waiting in the spool
+pub struct Spooled {
+    pub path: PathBuf,
+    pub profile: String,
+    pub reading: StoredReading,
+}
+
+impl Spool {
+    /// Spool in the directory, nothing is created until it is [opened](Spool::open)
+    pub fn new(dir: &Path) -> Spool {
+        Spool {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Spool in the subdirectory `name`
+    pub fn subdir(&self, name: &str) -> Spool {
+        Spool::new(&self.dir.join(name))
+    }
+
+    /// Create the directory if needed, before the spool is used
+    pub fn open(&self) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("cannot open `{}`: {}", self.dir.display(), e).into())
+    }
+
+    /// Durably write the measurement of the `profile` to the spool
+    pub fn push(
+        &self,
+        profile: &str,
+        reading: StoredReading,
+    ) -> Result<Spooled, Box<dyn std::error::Error>> {
+        let r = &reading;
+        let mut conf = Ini::new();
+        conf.with_section(Some("measurement".to_owned()))
+            .set("profile", profile)
+            .set("time", r.date_time.format(TIME_FORMAT).to_string())
+            .set("weight", r.weight.to_string())
+            .set("impedance", r.impedance.to_string())
+            .set("flags", r.flags.to_string());
+
+        // write to a temporary file first, so a crash never leaves a partial entry;
+        // the profile is read back from the file, the name only has to be safe
+        let name = format!(
+            "{}-{}",
+            r.date_time.format("%Y%m%dT%H%M%S"),
+            safe_name(profile)
+        );
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        let path = self.dir.join(format!("{}.ini", name));
+        let mut file = File::create(&tmp)?;
+        conf.write_to(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        Ok(Spooled {
+            path,
+            profile: profile.to_string(),
+            reading,
+        })
+    }
+
+    /// Spooled measurements, ordered by time
+    pub fn entries(&self) -> Result<Vec<Spooled>, Box<dyn std::error::Error>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "ini") {
+                continue;
+            }
+            let conf =
+                Ini::load_from_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let section = conf
+                .section(Some("measurement".to_owned()))
+                .ok_or(format!("{}: missing [measurement] section", path.display()))?;
+            let get = |key: &str| {
+                section
+                    .get(key)
+                    .ok_or(format!("{}: missing `{}`", path.display(), key))
+            };
+            let reading = StoredReading {
+                date_time: NaiveDateTime::parse_from_str(get("time")?, TIME_FORMAT)?,
+                weight: get("weight")?.parse()?,
+                impedance: get("impedance")?.parse()?,
+                flags: get("flags")?.parse()?,
+            };
+            entries.push(Spooled {
+                profile: get("profile")?.to_string(),
+                path,
+                reading,
+            });
+        }
+        entries.sort_by_key(|e| e.reading.date_time);
+
+        Ok(entries)
+    }
+
+    /// Remove the measurement after it was delivered
+    pub fn remove(&self, entry: &Spooled) -> Result<(), Box<dyn std::error::Error>> {
+        fs::remove_file(&entry.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn reading(hour: u32) -> StoredReading {
+        StoredReading {
+            date_time: NaiveDate::from_ymd_opt(2026, 10, 18)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap(),
+            weight: 75.3,
+            impedance: 500.0,
+            flags: 0x02,
+        }
+    }
+
+    fn spool(name: &str) -> Spool {
+        let dir =
+            std::env::temp_dir().join(format!("bluescale-spool-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let spool = Spool::new(&dir);
+        spool.open().unwrap();
+        spool
+    }
+
+    #[test]
+    fn push_entries_remove() {
+        let spool = spool("roundtrip");
+        spool.push("default", reading(9)).unwrap();
+        spool.push("default", reading(8)).unwrap();
+
+        let entries = spool.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].reading.date_time, reading(8).date_time);
+        assert_eq!(entries[0].profile, "default");
+        assert_eq!(entries[0].reading.weight, 75.3);
+        assert_eq!(entries[0].reading.impedance, 500.0);
+        assert_eq!(entries[0].reading.flags, 0x02);
+
+        spool.remove(&entries[0]).unwrap();
+        let entries = spool.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reading.date_time, reading(9).date_time);
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+
+    #[test]
+    fn profile_sanitized_in_file_name() {
+        let spool = spool("sanitized");
+        let entry = spool.push("../john doe:x", reading(8)).unwrap();
+        assert_eq!(entry.path.parent(), Some(spool.dir.as_path()));
+        assert_eq!(
+            entry.path.file_name().unwrap(),
+            "20261018T080000-___john_doe_x.ini"
+        );
+
+        let entries = spool.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].profile, "../john doe:x");
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+}
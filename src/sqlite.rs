@@ -0,0 +1,634 @@
+use crate::body::{Composition, Measurement, Person};
+use crate::calibration::{Metric, Reference};
+use crate::energy::EnergyBalance;
+use crate::goal::Progress;
+use crate::migration::{Migration, SQLITE_MIGRATIONS};
+use crate::storage::{
+    measurement_params, stored_measurement, stored_muscle_kg, stored_sample, MeasurementRow,
+    Quarantined, Storage, StoredComposition, StoredMeasurement, StoredReading, MEASUREMENT_COLUMNS,
+    SAMPLE_COLUMNS, STORED_COLUMNS,
+};
+use crate::trend::{Sample, Trend};
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+use rusqlite::{params, Connection, ToSql, TransactionBehavior};
+use simplelog::*;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// SQLite storage of the measurements in a local file, with the same tables
+/// and columns as the PostgreSQL [`Database`](crate::database::Database)
+pub struct Sqlite {
+    pub name: String,
+    /// apply the pending schema migrations at startup
+    pub auto_migrate: bool,
+    conn: Mutex<Connection>,
+}
+
+impl Sqlite {
+    /// Open (or create) the database file
+    pub fn open(name: &str, path: &Path) -> Result<Sqlite, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        Ok(Sqlite {
+            name: name.to_string(),
+            // unlike a (possibly shared) PostgreSQL database, the file belongs
+            // to bluescale and is created empty
+            auto_migrate: true,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, Box<dyn std::error::Error>> {
+        self.conn
+            .lock()
+            .map_err(|_| format!("{}: the connection is poisoned", self.name).into())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for Sqlite {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn auto_migrate(&self) -> bool {
+        self.auto_migrate
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE_MIGRATIONS
+    }
+
+    async fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.lock()?.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    async fn schema_version(&self) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let exists: bool = conn.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(0);
+        }
+
+        Ok(conn.query_row(
+            "SELECT coalesce(max(version), 0) FROM schema_version",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    async fn migrate(&self) -> Result<Vec<i32>, Box<dyn std::error::Error>> {
+        let mut conn = self.lock()?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                 version integer PRIMARY KEY,
+                 description text NOT NULL,
+                 applied text NOT NULL DEFAULT CURRENT_TIMESTAMP
+             )",
+        )?;
+
+        // concurrent migrations wait for each other
+        let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: i32 = transaction.query_row(
+            "SELECT coalesce(max(version), 0) FROM schema_version",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut applied = vec![];
+        for m in SQLITE_MIGRATIONS.iter().filter(|m| m.version > current) {
+            info!(
+                "{}: applying migration {}: {}",
+                self.name, m.version, m.description
+            );
+            transaction
+                .execute_batch(m.sql)
+                .map_err(|e| format!("migration {} has failed: {}", m.version, e))?;
+            transaction.execute(
+                "INSERT INTO schema_version (version, description) VALUES (?1, ?2)",
+                params![m.version, m.description],
+            )?;
+            applied.push(m.version);
+        }
+        transaction.commit()?;
+
+        Ok(applied)
+    }
+
+    async fn insert_measurement(
+        &self,
+        m: &Measurement,
+        p: &Person,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (table, columns, conflict) = match reason {
+            Some(_) => (
+                "mifit_quarantine",
+                format!("{}, reason", MEASUREMENT_COLUMNS),
+                "",
+            ),
+            None => (
+                "mifit",
+                MEASUREMENT_COLUMNS.to_string(),
+                " ON CONFLICT (profile, time) DO NOTHING",
+            ),
+        };
+        let row = MeasurementRow::new(m, p);
+        let mut params = measurement_params!(&row, dyn ToSql);
+        if let Some(reason) = &reason {
+            params.push(reason);
+        }
+        let values: Vec<String> = (1..=params.len()).map(|i| format!("?{}", i)).collect();
+
+        self.lock()?.execute(
+            &format!(
+                "INSERT INTO {} ({}) VALUES ({}){}",
+                table,
+                columns,
+                values.join(", "),
+                conflict
+            ),
+            params.as_slice(),
+        )?;
+
+        Ok(())
+    }
+
+    async fn fetch_quarantine(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Quarantined>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let mut statement = conn.prepare(
+            "SELECT id, time, weight, impedance, reason FROM mifit_quarantine
+                 WHERE profile = ?1 ORDER BY time",
+        )?;
+        let rows = statement.query_map([user], |row| {
+            Ok(Quarantined {
+                id: row.get(0)?,
+                date_time: row.get(1)?,
+                weight: row.get::<_, f64>(2)? as f32,
+                impedance: row.get::<_, Option<f64>>(3)?.unwrap_or_default() as f32,
+                reason: row.get(4)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn accept_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut conn = self.lock()?;
+        let transaction = conn.transaction()?;
+        let moved = transaction.execute(
+            &format!(
+                "INSERT INTO mifit ({0}) SELECT {0} FROM mifit_quarantine WHERE id = ?1",
+                MEASUREMENT_COLUMNS
+            ),
+            [id],
+        )?;
+        transaction.execute("DELETE FROM mifit_quarantine WHERE id = ?1", [id])?;
+        transaction.commit()?;
+
+        Ok(moved as u64)
+    }
+
+    async fn delete_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let deleted = self
+            .lock()?
+            .execute("DELETE FROM mifit_quarantine WHERE id = ?1", [id])?;
+        Ok(deleted as u64)
+    }
+
+    async fn fetch_readings(
+        &self,
+        user: &str,
+        since: Option<NaiveDate>,
+    ) -> Result<Vec<StoredReading>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let since = since.and_then(|d| d.and_hms_opt(0, 0, 0));
+        let mut statement = conn.prepare(
+            "SELECT time, weight, impedance, flags FROM mifit
+                 WHERE profile = ?1 AND impedance IS NOT NULL
+                   AND (?2 IS NULL OR time >= ?2)
+                 ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![user, since], |row| {
+            Ok(StoredReading {
+                date_time: row.get(0)?,
+                weight: row.get::<_, f64>(1)? as f32,
+                impedance: row.get::<_, f64>(2)? as f32,
+                flags: row.get::<_, Option<i32>>(3)?.unwrap_or_default() as u16,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn fetch_measurements(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredMeasurement>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT time, weight, flags, algorithm, {} FROM mifit
+                 WHERE profile = ?1
+                 ORDER BY time",
+            STORED_COLUMNS
+        ))?;
+        let rows = statement.query_map([user], |row| {
+            let mut values = [None; 22];
+            for (i, value) in values.iter_mut().enumerate() {
+                *value = row.get(i + 4)?;
+            }
+            Ok(stored_measurement(
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                values,
+            ))
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn fetch_compositions(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredComposition>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let mut statement = conn.prepare(
+            "SELECT time, weight, body_water_rate, metabolism, visceral_fat, fat_rate, muscle_kg, muscle_rate, bone_mass FROM mifit
+                 WHERE profile = ?1 AND impedance IS NULL
+                 ORDER BY time",
+        )?;
+        let rows = statement.query_map(params![user], |row| {
+            let value = |i: usize| -> rusqlite::Result<Option<f32>> {
+                Ok(row.get::<_, Option<f64>>(i)?.map(|v| v as f32))
+            };
+            let weight = row.get::<_, f64>(1)? as f32;
+            Ok(StoredComposition {
+                date_time: row.get(0)?,
+                weight,
+                composition: Composition {
+                    water_rate: value(2)?,
+                    bmr: value(3)?,
+                    visceral_fat: value(4)?,
+                    bf: value(5)?,
+                    muscle_kg: stored_muscle_kg(row.get(6)?, row.get(7)?, weight),
+                    bone_mass: value(8)?,
+                },
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn update_derived(
+        &self,
+        measurements: &[Measurement],
+        p: &Person,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut conn = self.lock()?;
+        let transaction = conn.transaction()?;
+        let mut updated = 0;
+        {
+            let mut statement = transaction.prepare(
+                "UPDATE mifit SET height = ?3, bmi = ?4, fat_rate = ?5, body_water_rate = ?6, bone_mass = ?7, metabolism = ?8, muscle_rate = ?9, visceral_fat = ?10, algorithm = ?11, muscle_kg = ?12, sex = ?13, age = ?14,
+                         fat_rate_calibrated = ?15, muscle_kg_calibrated = ?16, body_water_rate_calibrated = ?17, bone_mass_calibrated = ?18, visceral_fat_calibrated = ?19, skeletal_muscle_kg = ?20
+                     WHERE time = ?1 AND profile = ?2",
+            )?;
+            for m in measurements {
+                let c = m.calibrated;
+                let calibrated = [c.bf, c.muscle_kg, c.water_rate, c.bone_mass, c.visceral_fat]
+                    .map(|v| v.map(|v| v as f64));
+                updated += statement.execute(params![
+                    m.date_time,
+                    p.name,
+                    p.height as f64,
+                    m.bmi as f64,
+                    m.bf.map(|v| v as f64),
+                    m.water_rate.map(|v| v as f64),
+                    m.bone_mass.map(|v| v as f64),
+                    m.bmr as f64,
+                    m.muscle_rate.map(|v| v as f64),
+                    m.visceral_fat.map(|v| v as f64),
+                    m.sources.to_string(),
+                    m.muscle_kg.map(|v| v as f64),
+                    p.sex as i16,
+                    m.age as f64,
+                    calibrated[0],
+                    calibrated[1],
+                    calibrated[2],
+                    calibrated[3],
+                    calibrated[4],
+                    m.skeletal_muscle_kg.map(|v| v as f64),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(updated as u64)
+    }
+
+    async fn fetch_samples(
+        &self,
+        user: &str,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<Sample>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let mut statement = conn.prepare(&format!(
+            "SELECT time, {} FROM mifit
+                 WHERE profile = ?1
+                   AND (?2 IS NULL OR time >= ?2)
+                 ORDER BY time",
+            SAMPLE_COLUMNS
+        ))?;
+        let rows = statement.query_map(params![user, since], |row| {
+            let mut values = [None; 6];
+            for (i, value) in values.iter_mut().enumerate() {
+                *value = row.get(i + 2)?;
+            }
+            Ok(stored_sample(row.get(0)?, row.get(1)?, values))
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    async fn update_trends(
+        &self,
+        user: &str,
+        samples: &[Sample],
+        trends: &[Trend],
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut conn = self.lock()?;
+        let transaction = conn.transaction()?;
+        let mut updated = 0;
+        {
+            let mut statement = transaction.prepare(
+                "UPDATE mifit SET weight_trend = ?3, weight_avg7 = ?4, weight_avg30 = ?5, weight_week_change = ?6, fat_week_change = ?7, muscle_week_change = ?8
+                     WHERE time = ?1 AND profile = ?2",
+            )?;
+            for (s, t) in samples.iter().zip(trends) {
+                updated += statement.execute(params![
+                    s.date_time,
+                    user,
+                    t.weight as f64,
+                    t.weight_avg_7d as f64,
+                    t.weight_avg_30d as f64,
+                    t.weight_rate.map(|v| v as f64),
+                    t.fat_rate.map(|v| v as f64),
+                    t.muscle_rate.map(|v| v as f64),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(updated as u64)
+    }
+
+    async fn insert_progress(
+        &self,
+        user: &str,
+        progress: &[Progress],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        for pr in progress {
+            conn.execute(
+                "INSERT INTO goal_progress (time, profile, metric, target, current, remaining, weekly_rate, projected)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                     ON CONFLICT (profile, time, metric) DO NOTHING",
+                params![
+                    pr.date_time,
+                    user,
+                    pr.goal.metric.to_string(),
+                    pr.goal.target as f64,
+                    pr.current as f64,
+                    pr.remaining as f64,
+                    pr.weekly_rate.map(|v| v as f64),
+                    pr.projected,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    async fn upsert_energy_weekly(
+        &self,
+        user: &str,
+        weeks: &[(NaiveDate, EnergyBalance)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        for (week, e) in weeks {
+            conn.execute(
+                "INSERT INTO energy_weekly (profile, week, bmr, tdee, fat_mass_change, lean_mass_change, balance, intake)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                     ON CONFLICT (profile, week) DO UPDATE SET bmr = ?3, tdee = ?4, fat_mass_change = ?5, lean_mass_change = ?6, balance = ?7, intake = ?8",
+                params![
+                    user,
+                    week,
+                    e.bmr as f64,
+                    e.tdee as f64,
+                    e.fat_mass_rate as f64,
+                    e.lean_mass_rate as f64,
+                    e.balance as f64,
+                    e.intake as f64,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_reference(
+        &self,
+        user: &str,
+        date: NaiveDate,
+        metric: Metric,
+        value: f32,
+        source: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.lock()?.execute(
+            "INSERT INTO reference_measurement (profile, date, metric, value, source) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user, date, metric.to_string(), value as f64, source],
+        )?;
+
+        Ok(())
+    }
+
+    async fn fetch_references(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Reference>, Box<dyn std::error::Error>> {
+        let conn = self.lock()?;
+        let mut statement = conn.prepare(
+            "SELECT id, date, metric, value, source FROM reference_measurement WHERE profile = ?1 ORDER BY date",
+        )?;
+        let mut rows = statement.query([user])?;
+
+        let mut references = vec![];
+        while let Some(row) = rows.next()? {
+            references.push(Reference {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                metric: row.get::<_, String>(2)?.parse()?,
+                value: row.get::<_, f64>(3)? as f32,
+                source: row.get(4)?,
+            });
+        }
+
+        Ok(references)
+    }
+
+    async fn delete_reference(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>> {
+        let deleted = self
+            .lock()?
+            .execute("DELETE FROM reference_measurement WHERE id = ?1", [id])?;
+        Ok(deleted as u64)
+    }
+
+    async fn fetch_scale_average(
+        &self,
+        user: &str,
+        metric: Metric,
+        date: NaiveDate,
+    ) -> Result<Option<f32>, Box<dyn std::error::Error>> {
+        let average: Option<f64> = self.lock()?.query_row(
+            &format!(
+                "SELECT avg({}) FROM mifit
+                     WHERE profile = ?1 AND abs(julianday(time) - julianday(?2)) <= 3",
+                metric.column()
+            ),
+            params![user, date],
+            |row| row.get(0),
+        )?;
+
+        Ok(average.map(|v| v as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn person() -> Person {
+        crate::body::tests::person(NaiveDate::from_ymd_opt(1990, 6, 15).unwrap())
+    }
+
+    fn at(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap()
+    }
+
+    fn database() -> Sqlite {
+        let db = Sqlite::open("test", Path::new(":memory:")).unwrap();
+        block_on(db.migrate()).unwrap();
+        db
+    }
+
+    #[test]
+    fn migrations_applied_once() {
+        let db = Sqlite::open("test", Path::new(":memory:")).unwrap();
+        assert_eq!(block_on(db.schema_version()).unwrap(), 0);
+
+        let applied = block_on(db.migrate()).unwrap();
+        let all: Vec<i32> = SQLITE_MIGRATIONS.iter().map(|m| m.version).collect();
+        assert_eq!(applied, all);
+        assert_eq!(block_on(db.schema_version()).unwrap(), db.latest_version());
+        assert!(block_on(db.migrate()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn insert_and_fetch() {
+        let db = database();
+        let p = person();
+        let m = p.measure(at(17), 80.0, 500.0, 0x02);
+        block_on(db.insert_measurement(&m, &p, None)).unwrap();
+
+        let readings = block_on(db.fetch_readings("test", None)).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].date_time, at(17));
+        assert_eq!(readings[0].weight, 80.0);
+        assert_eq!(readings[0].impedance, 500.0);
+        assert_eq!(readings[0].flags, 0x02);
+
+        let samples = block_on(db.fetch_samples("test", None)).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].fat, m.bf);
+        assert_eq!(samples[0].muscle, m.muscle_kg);
+        assert_eq!(samples[0].bmr, Some(m.bmr));
+        assert!(block_on(db.fetch_samples("other", None))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn duplicate_insert_skipped() {
+        let db = database();
+        let p = person();
+        let m = p.measure(at(17), 80.0, 500.0, 0);
+        block_on(db.insert_measurement(&m, &p, None)).unwrap();
+        let replayed = p.measure(at(17), 81.0, 500.0, 0);
+        block_on(db.insert_measurement(&replayed, &p, None)).unwrap();
+
+        let samples = block_on(db.fetch_samples("test", None)).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].weight, 80.0);
+    }
+
+    #[test]
+    fn fetch_samples_of_legacy_rows() {
+        let db = database();
+        // stored without the metabolism and with the muscle rate only
+        db.lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO mifit (time, weight, muscle_rate, profile) VALUES (?1, 80.0, 50.0, 'test')",
+                [at(16)],
+            )
+            .unwrap();
+        let p = person();
+        block_on(db.insert_measurement(&p.measure(at(17), 80.0, 500.0, 0), &p, None)).unwrap();
+
+        let samples = block_on(db.fetch_samples("test", None)).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].bmr, None);
+        assert_eq!(samples[0].muscle, Some(40.0));
+        assert_eq!(samples[0].impedance, None);
+
+        let since = block_on(db.fetch_samples("test", Some(at(17)))).unwrap();
+        assert_eq!(since.len(), 1);
+        assert_eq!(since[0].date_time, at(17));
+    }
+
+    #[test]
+    fn quarantine_accepted() {
+        let db = database();
+        let p = person();
+        let m = p.measure(at(17), 95.0, 500.0, 0);
+        block_on(db.insert_measurement(&m, &p, Some("weight jump"))).unwrap();
+        assert!(block_on(db.fetch_samples("test", None)).unwrap().is_empty());
+
+        let quarantined = block_on(db.fetch_quarantine("test")).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].reason, "weight jump");
+        assert_eq!(
+            block_on(db.accept_quarantined(quarantined[0].id)).unwrap(),
+            1
+        );
+        assert!(block_on(db.fetch_quarantine("test")).unwrap().is_empty());
+        assert_eq!(block_on(db.fetch_samples("test", None)).unwrap().len(), 1);
+    }
+}
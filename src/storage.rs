@@ -0,0 +1,396 @@
+use crate::body::{Composition, Measurement, Person};
+use crate::calibration::{Calibrated, Calibration, Metric, Reference};
+use crate::energy::EnergyBalance;
+use crate::goal::Progress;
+use crate::migration::Migration;
+use crate::trend::{Sample, Trend};
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Columns set when storing a measurement (`mifit` and `mifit_quarantine` tables)
+pub(crate) const MEASUREMENT_COLUMNS: &str = "time, weight, height, bmi, fat_rate, body_water_rate, bone_mass, metabolism, muscle_rate, visceral_fat, algorithm, impedance, muscle_kg, sex, age, profile, flags, weight_trend, weight_avg7, weight_avg30, weight_week_change, fat_week_change, muscle_week_change, fat_rate_calibrated, muscle_kg_calibrated, body_water_rate_calibrated, bone_mass_calibrated, visceral_fat_calibrated, skeletal_muscle_kg";
+
+/// Columns read into a [`Sample`], after the time
+pub(crate) const SAMPLE_COLUMNS: &str =
+    "weight, fat_rate, muscle_kg, muscle_rate, weight_trend, impedance, metabolism";
+
+/// Columns read into a [`StoredMeasurement`], after the time, the weight, the flags and the algorithm
+pub(crate) const STORED_COLUMNS: &str = "impedance, age, bmi, body_water_rate, metabolism, visceral_fat, fat_rate, muscle_kg, muscle_rate, skeletal_muscle_kg, bone_mass, weight_trend, weight_avg7, weight_avg30, weight_week_change, fat_week_change, muscle_week_change, fat_rate_calibrated, muscle_kg_calibrated, body_water_rate_calibrated, bone_mass_calibrated, visceral_fat_calibrated";
+
+/// Values of the [`MEASUREMENT_COLUMNS`] of a measurement, as stored by both backends
+pub(crate) struct MeasurementRow<'a> {
+    pub date_time: NaiveDateTime,
+    pub weight: f64,
+    pub height: f64,
+    pub bmi: f64,
+    pub bf: Option<f64>,
+    pub water_rate: Option<f64>,
+    pub bone_mass: Option<f64>,
+    pub bmr: f64,
+    pub muscle_rate: Option<f64>,
+    pub visceral_fat: Option<f64>,
+    pub sources: String,
+    pub impedance: Option<f64>,
+    pub muscle_kg: Option<f64>,
+    pub sex: i16,
+    pub age: f64,
+    pub profile: &'a str,
+    pub flags: i32,
+    pub weight_trend: Option<f64>,
+    pub weight_avg7: Option<f64>,
+    pub weight_avg30: Option<f64>,
+    pub weight_change: Option<f64>,
+    pub fat_change: Option<f64>,
+    pub muscle_change: Option<f64>,
+    pub calibrated: [Option<f64>; 5],
+    pub skeletal_muscle_kg: Option<f64>,
+}
+
+impl MeasurementRow<'_> {
+    /// An unknown impedance (0) is stored as NULL
+    pub fn new<'a>(m: &Measurement, p: &'a Person) -> MeasurementRow<'a> {
+        let f = |v: f32| v as f64;
+        let rate = |r: Option<f32>| r.map(f);
+        let t = m.trend;
+        let c = m.calibrated;
+        MeasurementRow {
+            date_time: m.date_time,
+            weight: f(m.weight),
+            height: f(p.height),
+            bmi: f(m.bmi),
+            bf: rate(m.bf),
+            water_rate: rate(m.water_rate),
+            bone_mass: rate(m.bone_mass),
+            bmr: f(m.bmr),
+            muscle_rate: rate(m.muscle_rate),
+            visceral_fat: rate(m.visceral_fat),
+            sources: m.sources.to_string(),
+            impedance: (m.impedance > 0.0).then(|| f(m.impedance)),
+            muscle_kg: rate(m.muscle_kg),
+            sex: p.sex as i16,
+            age: f(m.age),
+            profile: &p.name,
+            flags: m.flags as i32,
+            weight_trend: t.map(|t| f(t.weight)),
+            weight_avg7: t.map(|t| f(t.weight_avg_7d)),
+            weight_avg30: t.map(|t| f(t.weight_avg_30d)),
+            weight_change: t.and_then(|t| rate(t.weight_rate)),
+            fat_change: t.and_then(|t| rate(t.fat_rate)),
+            muscle_change: t.and_then(|t| rate(t.muscle_rate)),
+            calibrated: [c.bf, c.muscle_kg, c.water_rate, c.bone_mass, c.visceral_fat].map(rate),
+            skeletal_muscle_kg: rate(m.skeletal_muscle_kg),
+        }
+    }
+}
+
+/// Parameters of the [`MeasurementRow`] in the order of the [`MEASUREMENT_COLUMNS`],
+/// as references to the `ToSql` trait object of the backend
+macro_rules! measurement_params {
+    ($row:expr, $to_sql:ty) => {{
+        let r = $row;
+        let params: Vec<&$to_sql> = vec![
+            &r.date_time,
+            &r.weight,
+            &r.height,
+            &r.bmi,
+            &r.bf,
+            &r.water_rate,
+            &r.bone_mass,
+            &r.bmr,
+            &r.muscle_rate,
+            &r.visceral_fat,
+            &r.sources,
+            &r.impedance,
+            &r.muscle_kg,
+            &r.sex,
+            &r.age,
+            &r.profile,
+            &r.flags,
+            &r.weight_trend,
+            &r.weight_avg7,
+            &r.weight_avg30,
+            &r.weight_change,
+            &r.fat_change,
+            &r.muscle_change,
+            &r.calibrated[0],
+            &r.calibrated[1],
+            &r.calibrated[2],
+            &r.calibrated[3],
+            &r.calibrated[4],
+            &r.skeletal_muscle_kg,
+        ];
+        params
+    }};
+}
+pub(crate) use measurement_params;
+
+/// Muscle mass (kg) of a stored measurement, the rows stored before
+/// `muscle_kg` was added only have the rate
+pub(crate) fn stored_muscle_kg(kg: Option<f64>, rate: Option<f64>, weight: f32) -> Option<f32> {
+    kg.map(|kg| kg as f32)
+        .or_else(|| rate.map(|rate| rate as f32 * weight / 100.0))
+}
+
+/// Measurement from the [`STORED_COLUMNS`] of a stored measurement
+pub(crate) fn stored_measurement(
+    date_time: NaiveDateTime,
+    weight: f64,
+    flags: Option<i32>,
+    algorithm: Option<String>,
+    values: [Option<f64>; 22],
+) -> StoredMeasurement {
+    let weight = weight as f32;
+    let v = values.map(|v| v.map(|v| v as f32));
+    let trend = match (v[11], v[12], v[13]) {
+        (Some(weight), Some(weight_avg_7d), Some(weight_avg_30d)) => Some(Trend {
+            weight,
+            weight_avg_7d,
+            weight_avg_30d,
+            weight_rate: v[14],
+            fat_rate: v[15],
+            muscle_rate: v[16],
+        }),
+        _ => None,
+    };
+    StoredMeasurement {
+        date_time,
+        weight,
+        impedance: v[0],
+        flags: flags.unwrap_or_default() as u16,
+        age: v[1],
+        bmi: v[2],
+        composition: Composition {
+            water_rate: v[3],
+            bmr: v[4],
+            visceral_fat: v[5],
+            bf: v[6],
+            muscle_kg: stored_muscle_kg(values[7], values[8], weight),
+            bone_mass: v[10],
+        },
+        muscle_rate: v[8],
+        skeletal_muscle_kg: v[9],
+        algorithm,
+        trend,
+        calibrated: Calibrated {
+            bf: v[17],
+            muscle_kg: v[18],
+            water_rate: v[19],
+            bone_mass: v[20],
+            visceral_fat: v[21],
+        },
+    }
+}
+
+/// Sample from the [`SAMPLE_COLUMNS`] of a stored measurement
+pub(crate) fn stored_sample(
+    date_time: NaiveDateTime,
+    weight: f64,
+    [fat, muscle_kg, muscle_rate, trend_weight, impedance, bmr]: [Option<f64>; 6],
+) -> Sample {
+    let weight = weight as f32;
+    let f = |v: Option<f64>| v.map(|v| v as f32);
+    Sample {
+        date_time,
+        weight,
+        fat: f(fat),
+        muscle: stored_muscle_kg(muscle_kg, muscle_rate, weight),
+        trend_weight: f(trend_weight),
+        impedance: f(impedance),
+        bmr: f(bmr),
+    }
+}
+
+/// Measurement rejected as an outlier
+pub struct Quarantined {
+    pub id: i32,
+    pub date_time: NaiveDateTime,
+    pub weight: f32,
+    pub impedance: f32,
+    pub reason: String,
+}
+
+/// Raw scale inputs of a stored measurement
+pub struct StoredReading {
+    pub date_time: NaiveDateTime,
+    pub weight: f32,
+    pub impedance: f32,
+    pub flags: u16,
+}
+
+/// Stored measurement with the values as stored, the ones missing in the older rows are `None`
+pub struct StoredMeasurement {
+    pub date_time: NaiveDateTime,
+    pub weight: f32,
+    pub impedance: Option<f32>,
+    pub flags: u16,
+    pub age: Option<f32>,
+    pub bmi: Option<f32>,
+    pub composition: Composition,
+    pub muscle_rate: Option<f32>,
+    pub skeletal_muscle_kg: Option<f32>,
+    /// names of the algorithm sets, see [`Sources::parse`](crate::body::Sources::parse)
+    pub algorithm: Option<String>,
+    pub trend: Option<Trend>,
+    pub calibrated: Calibrated,
+}
+
+/// Stored measurement without the impedance (eg. imported) with its body composition values
+pub struct StoredComposition {
+    pub date_time: NaiveDateTime,
+    pub weight: f32,
+    pub composition: Composition,
+}
+
+/// Storage of the measurements and the derived data, PostgreSQL ([`Database`](crate::database::Database))
+/// or SQLite ([`Sqlite`](crate::sqlite::Sqlite)), selected in the config
+#[async_trait(?Send)]
+pub trait Storage {
+    fn name(&self) -> &str;
+
+    /// Apply the pending schema migrations at startup
+    fn auto_migrate(&self) -> bool;
+
+    /// Schema migrations of the backend
+    fn migrations(&self) -> &'static [Migration];
+
+    /// Version of the schema the code expects
+    fn latest_version(&self) -> i32 {
+        self.migrations().last().map_or(0, |m| m.version)
+    }
+
+    /// Check that the storage is reachable (and the credentials work)
+    async fn check(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Whether the error of [`check`](Storage::check) is temporary (eg. the server is down),
+    /// rather than caused by the config or the credentials
+    fn is_unreachable(&self, _e: &(dyn std::error::Error + 'static)) -> bool {
+        false
+    }
+
+    /// Version of the schema, 0 for a database without the `schema_version` table
+    async fn schema_version(&self) -> Result<i32, Box<dyn std::error::Error>>;
+
+    /// Apply the pending schema migrations in a single transaction,
+    /// returns the versions applied
+    async fn migrate(&self) -> Result<Vec<i32>, Box<dyn std::error::Error>>;
+
+    /// Insert the measurement into the `mifit` table, or into the `mifit_quarantine`
+    /// table with the `reason`. A measurement already stored (eg. replayed from the spool) is skipped.
+    /// An unknown impedance (0) is stored as NULL.
+    async fn insert_measurement(
+        &self,
+        m: &Measurement,
+        p: &Person,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Quarantined measurements of the `user`
+    async fn fetch_quarantine(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Quarantined>, Box<dyn std::error::Error>>;
+
+    /// Move the quarantined measurement to the `mifit` table
+    async fn accept_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Delete the quarantined measurement
+    async fn delete_quarantined(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Raw inputs of the stored measurements of the `user`, optionally since the given date
+    async fn fetch_readings(
+        &self,
+        user: &str,
+        since: Option<NaiveDate>,
+    ) -> Result<Vec<StoredReading>, Box<dyn std::error::Error>>;
+
+    /// Stored measurements of the `user` with all their values, ordered by time
+    async fn fetch_measurements(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredMeasurement>, Box<dyn std::error::Error>>;
+
+    /// Stored measurements of the `user` without the impedance, ordered by time
+    async fn fetch_compositions(
+        &self,
+        user: &str,
+    ) -> Result<Vec<StoredComposition>, Box<dyn std::error::Error>>;
+
+    /// Overwrite the derived columns (and the profile used) of stored measurements
+    async fn update_derived(
+        &self,
+        measurements: &[Measurement],
+        p: &Person,
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Stored measurements of the `user` since the given time, ordered by time
+    async fn fetch_samples(
+        &self,
+        user: &str,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<Sample>, Box<dyn std::error::Error>>;
+
+    /// Overwrite the stored trend columns
+    async fn update_trends(
+        &self,
+        user: &str,
+        samples: &[Sample],
+        trends: &[Trend],
+    ) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Store the goal progress of the `user`, the progress already stored at the time is kept
+    async fn insert_progress(
+        &self,
+        user: &str,
+        progress: &[Progress],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Store (or overwrite) the weekly energy balance figures of the `user`
+    async fn upsert_energy_weekly(
+        &self,
+        user: &str,
+        weeks: &[(NaiveDate, EnergyBalance)],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Store a reference measurement of the `user`
+    async fn insert_reference(
+        &self,
+        user: &str,
+        date: NaiveDate,
+        metric: Metric,
+        value: f32,
+        source: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Reference measurements of the `user`
+    async fn fetch_references(
+        &self,
+        user: &str,
+    ) -> Result<Vec<Reference>, Box<dyn std::error::Error>>;
+
+    /// Delete the reference measurement
+    async fn delete_reference(&self, id: i32) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Average (uncalibrated) scale value of the `metric` within 3 days from the `date`
+    async fn fetch_scale_average(
+        &self,
+        user: &str,
+        metric: Metric,
+        date: NaiveDate,
+    ) -> Result<Option<f32>, Box<dyn std::error::Error>>;
+
+    /// Fit the calibration of the `user` pairing each reference measurement with
+    /// the average scale value within 3 days from it
+    async fn fetch_calibration(
+        &self,
+        user: &str,
+    ) -> Result<Calibration, Box<dyn std::error::Error>> {
+        let mut pairs = vec![];
+        for r in self.fetch_references(user).await? {
+            if let Some(scale) = self.fetch_scale_average(user, r.metric, r.date).await? {
+                pairs.push((r.metric, scale, r.value));
+            }
+        }
+
+        Ok(Calibration::fit(&pairs))
+    }
+}
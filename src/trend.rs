@@ -0,0 +1,240 @@
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+use std::fmt;
+
+/// Smoothing factor of the daily exponentially smoothed trend (Hacker's Diet uses 10%)
+pub const SMOOTHING: f32 = 0.1;
+
+/// Time window of the rate of change
+pub const RATE_WINDOW_DAYS: i64 = 30;
+
+/// A stored measurement as used for the trend analysis
+#[derive(Clone)]
+pub struct Sample {
+    pub date_time: NaiveDateTime,
+    pub weight: f32,
+    /// body fat (%), not estimated for children
+    pub fat: Option<f32>,
+    /// muscle mass (kg), not estimated for children
+    pub muscle: Option<f32>,
+    /// impedance (Ω), if stored
+    pub impedance: Option<f32>,
+    /// basal metabolism (kcal), if stored
+    pub bmr: Option<f32>,
+    /// stored trend weight, if any
+    pub trend_weight: Option<f32>,
+}
+
+/// Smoothed weight, moving averages and weekly rates of change at a measurement
+#[derive(Clone, Copy, Serialize)]
+pub struct Trend {
+    /// exponentially smoothed weight (kg)
+    pub weight: f32,
+    /// moving average of the weight over the last 7 days (kg)
+    pub weight_avg_7d: f32,
+    /// moving average of the weight over the last 30 days (kg)
+    pub weight_avg_30d: f32,
+    /// weight change per week (kg)
+    pub weight_rate: Option<f32>,
+    /// body fat change per week (percentage points)
+    pub fat_rate: Option<f32>,
+    /// muscle mass change per week (kg)
+    pub muscle_rate: Option<f32>,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rate = |r: Option<f32>| match r {
+            Some(r) => format!("{:+.2}", r),
+            None => "-".to_string(),
+        };
+        write!(
+            f,
+            "trend: {:.2} kg, 7d avg: {:.2} kg, 30d avg: {:.2} kg, weekly change: weight {} kg, fat {} %, muscle {} kg",
+            self.weight,
+            self.weight_avg_7d,
+            self.weight_avg_30d,
+            rate(self.weight_rate),
+            rate(self.fat_rate),
+            rate(self.muscle_rate)
+        )
+    }
+}
+
+/// Average of the values of the samples taken within `days` before `now`
+fn moving_average(samples: &[&Sample], now: NaiveDateTime, days: i64) -> f32 {
+    let window: Vec<f32> = samples
+        .iter()
+        .filter(|s| s.date_time > now - Duration::days(days))
+        .map(|s| s.weight)
+        .collect();
+    window.iter().sum::<f32>() / window.len() as f32
+}
+
+/// Least-squares slope of the value per week (samples without the value are skipped)
+pub fn weekly_rate<F>(samples: &[&Sample], value: F) -> Option<f32>
+where
+    F: Fn(&Sample) -> Option<f32>,
+{
+    let t0 = samples.first()?.date_time;
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .filter_map(|s| {
+            value(s).map(|v| {
+                (
+                    (s.date_time - t0).num_seconds() as f64 / (7 * 24 * 3600) as f64,
+                    v as f64,
+                )
+            })
+        })
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+
+    Some((sxy / sxx) as f32)
+}
+
+impl Trend {
+    /// Compute the trend at the `current` measurement from the previous samples (ordered by time)
+    pub fn compute(history: &[Sample], current: &Sample) -> Trend {
+        let weight = match history.last() {
+            Some(prev) => {
+                let prev_trend = prev.trend_weight.unwrap_or(prev.weight);
+                let days = (current.date_time - prev.date_time).num_seconds() as f32 / 86400.0;
+                let alpha = 1.0 - (1.0 - SMOOTHING).powf(days.max(0.0));
+                prev_trend + alpha * (current.weight - prev_trend)
+            }
+            None => current.weight,
+        };
+
+        let window: Vec<&Sample> = history
+            .iter()
+            .filter(|s| s.date_time > current.date_time - Duration::days(RATE_WINDOW_DAYS))
+            .chain(std::iter::once(current))
+            .collect();
+
+        Trend {
+            weight,
+            weight_avg_7d: moving_average(&window, current.date_time, 7),
+            weight_avg_30d: moving_average(&window, current.date_time, 30),
+            weight_rate: weekly_rate(&window, |s| Some(s.weight)),
+            fat_rate: weekly_rate(&window, |s| s.fat),
+            muscle_rate: weekly_rate(&window, |s| s.muscle),
+        }
+    }
+
+    /// Compute the trends of all samples (ordered by time), ignoring the stored trend weights
+    pub fn series(samples: &[Sample]) -> Vec<Trend> {
+        let mut history: Vec<Sample> = Vec::with_capacity(samples.len());
+        let mut trends = Vec::with_capacity(samples.len());
+        for s in samples {
+            let mut s = s.clone();
+            let t = Trend::compute(&history, &s);
+            s.trend_weight = Some(t.weight);
+            history.push(s);
+            trends.push(t);
+        }
+        trends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn sample(day: u32, weight: f32, fat: Option<f32>) -> Sample {
+        Sample {
+            date_time: NaiveDate::from_ymd_opt(2023, 1, day)
+                .unwrap()
+                .and_hms_opt(7, 0, 0)
+                .unwrap(),
+            weight,
+            fat,
+            muscle: None,
+            impedance: None,
+            bmr: Some(1700.0),
+            trend_weight: None,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn first_sample() {
+        let t = Trend::compute(&[], &sample(1, 80.0, Some(20.0)));
+        assert_eq!(t.weight, 80.0);
+        assert_eq!(t.weight_avg_7d, 80.0);
+        assert_eq!(t.weight_avg_30d, 80.0);
+        assert_eq!(t.weight_rate, None);
+        assert_eq!(t.fat_rate, None);
+    }
+
+    #[test]
+    fn smoothing_by_elapsed_days() {
+        let mut prev = sample(1, 80.0, None);
+        // a day later the trend moves by the smoothing factor
+        let t = Trend::compute(&[prev.clone()], &sample(2, 81.0, None));
+        assert!(close(t.weight, 80.1));
+        // after two days by the factor compounded
+        let t = Trend::compute(&[prev.clone()], &sample(3, 81.0, None));
+        assert!(close(t.weight, 80.0 + 0.19));
+        // from the stored trend weight of the previous sample
+        prev.trend_weight = Some(79.0);
+        let t = Trend::compute(&[prev], &sample(2, 81.0, None));
+        assert!(close(t.weight, 79.2));
+    }
+
+    #[test]
+    fn moving_averages() {
+        let history = [sample(1, 90.0, None), sample(20, 80.0, None)];
+        let t = Trend::compute(&history, &sample(25, 82.0, None));
+        assert_eq!(t.weight_avg_7d, 81.0);
+        assert!(close(t.weight_avg_30d, 84.0));
+    }
+
+    #[test]
+    fn weekly_rates() {
+        // 0.1 kg and 0.05 % a day, the body fat missing in one sample
+        let samples: Vec<Sample> = (1..=15)
+            .map(|day| {
+                let fat = (day != 8).then_some(25.0 - 0.05 * day as f32);
+                sample(day, 80.0 + 0.1 * day as f32, fat)
+            })
+            .collect();
+        let trends = Trend::series(&samples);
+        let last = trends.last().unwrap();
+        assert!(close(last.weight_rate.unwrap(), 0.7));
+        assert!(close(last.fat_rate.unwrap(), -0.35));
+        assert_eq!(last.muscle_rate, None);
+        assert_eq!(trends[0].weight_rate, None);
+    }
+
+    #[test]
+    fn weekly_rate_needs_two_values() {
+        let samples = [sample(1, 80.0, Some(20.0)), sample(2, 80.0, None)];
+        let window: Vec<&Sample> = samples.iter().collect();
+        assert_eq!(weekly_rate(&window, |s| s.fat), None);
+        assert_eq!(weekly_rate(&window, |s| Some(s.weight)), Some(0.0));
+    }
+
+    #[test]
+    fn series_ignores_stored_trend() {
+        let mut samples = vec![sample(1, 80.0, None), sample(2, 81.0, None)];
+        samples[0].trend_weight = Some(70.0);
+        let trends = Trend::series(&samples);
+        assert_eq!(trends[0].weight, 80.0);
+        assert!(close(trends[1].weight, 80.1));
+    }
+}
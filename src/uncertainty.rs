@@ -0,0 +1,156 @@
+use crate::body::Measurement;
+use serde::Serialize;
+
+/// Coverage factor of the reported intervals (95% confidence)
+pub const COVERAGE: f32 = 1.96;
+
+/// Standard uncertainty of the scale inputs
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct InputUncertainty {
+    /// weight (kg)
+    pub weight: f32,
+    /// impedance (Ω)
+    pub impedance: f32,
+}
+
+impl Default for InputUncertainty {
+    fn default() -> Self {
+        InputUncertainty {
+            weight: 0.05,
+            impedance: 10.0,
+        }
+    }
+}
+
+/// Half-widths of the 95% confidence intervals of the computed values
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Uncertainty {
+    pub weight: f32,
+    pub bmi: f32,
+    pub water_rate: f32,
+    pub bmr: f32,
+    pub visceral_fat: f32,
+    pub bf: f32,
+    pub muscle_kg: f32,
+    pub muscle_rate: f32,
+    pub bone_mass: f32,
+}
+
+impl InputUncertainty {
+    /// Propagate the input uncertainty through the formulas.
+    ///
+    /// `eval(weight, impedance)` evaluates the formulas, the sensitivities are
+    /// estimated by central differences over the input uncertainty (which also
+    /// covers the thresholds in the formulas) and combined in quadrature.
+    pub fn propagate<F>(&self, m: &Measurement, eval: F) -> Uncertainty
+    where
+        F: Fn(f32, f32) -> Measurement,
+    {
+        let sensitivity = |lo: &Measurement, hi: &Measurement, value: fn(&Measurement) -> f32| {
+            (value(hi) - value(lo)) / 2.0
+        };
+        let (w_lo, w_hi) = (
+            eval(m.weight - self.weight, m.impedance),
+            eval(m.weight + self.weight, m.impedance),
+        );
+        let (z_lo, z_hi) = (
+            eval(m.weight, m.impedance - self.impedance),
+            eval(m.weight, m.impedance + self.impedance),
+        );
+        let combine = |value: fn(&Measurement) -> f32| {
+            let dw = sensitivity(&w_lo, &w_hi, value);
+            let dz = sensitivity(&z_lo, &z_hi, value);
+            COVERAGE * (dw * dw + dz * dz).sqrt()
+        };
+
+        Uncertainty {
+            weight: COVERAGE * self.weight,
+            bmi: combine(|m| m.bmi),
+            water_rate: combine(|m| m.water_rate.unwrap_or_default()),
+            bmr: combine(|m| m.bmr),
+            visceral_fat: combine(|m| m.visceral_fat.unwrap_or_default()),
+            bf: combine(|m| m.bf.unwrap_or_default()),
+            muscle_kg: combine(|m| m.muscle_kg.unwrap_or_default()),
+            muscle_rate: combine(|m| m.muscle_rate.unwrap_or_default()),
+            bone_mass: combine(|m| m.bone_mass.unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Person;
+    use crate::outlier::OutlierFilter;
+    use chrono::NaiveDate;
+
+    fn person(birthday: NaiveDate, uncertainty: InputUncertainty) -> Person {
+        Person {
+            name: "test".to_string(),
+            sex: 1,
+            birthday,
+            height: 180.0,
+            algorithms: vec![],
+            goals: vec![],
+            outlier: OutlierFilter::default(),
+            activity: 1.2,
+            uncertainty,
+        }
+    }
+
+    fn measure(p: &Person) -> Uncertainty {
+        let date_time = NaiveDate::from_ymd_opt(2023, 1, 1)
+            .unwrap()
+            .and_hms_opt(7, 0, 0)
+            .unwrap();
+        p.measure(date_time, 80.0, 500.0, 0).uncertainty.unwrap()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    fn adult(weight: f32, impedance: f32) -> Uncertainty {
+        let birthday = NaiveDate::from_ymd_opt(1990, 1, 1).unwrap();
+        measure(&person(birthday, InputUncertainty { weight, impedance }))
+    }
+
+    #[test]
+    fn linear_in_weight() {
+        let u = adult(0.05, 10.0);
+        assert!(close(u.weight, COVERAGE * 0.05));
+        // BMI = weight / 1.8², not depending on the impedance
+        assert!(close(u.bmi, COVERAGE * 0.05 / 3.24));
+        // MiFit BMR of men: 14.916 kcal per kg
+        assert!(close(u.bmr, COVERAGE * 0.05 * 14.916));
+    }
+
+    #[test]
+    fn exact_inputs() {
+        let u = adult(0.0, 0.0);
+        assert_eq!(u.weight, 0.0);
+        assert_eq!(u.bmi, 0.0);
+        assert_eq!(u.bf, 0.0);
+        assert_eq!(u.muscle_kg, 0.0);
+    }
+
+    #[test]
+    fn inputs_combined_in_quadrature() {
+        let weight_only = adult(0.05, 0.0).bf;
+        let impedance_only = adult(0.0, 10.0).bf;
+        let both = adult(0.05, 10.0).bf;
+        assert!(weight_only > 0.0 && impedance_only > 0.0);
+        assert!(close(both, weight_only.hypot(impedance_only)));
+        // a larger impedance uncertainty gives a wider interval
+        assert!(adult(0.0, 20.0).bf > impedance_only);
+    }
+
+    #[test]
+    fn values_not_estimated() {
+        let birthday = NaiveDate::from_ymd_opt(2015, 1, 1).unwrap();
+        let u = measure(&person(birthday, InputUncertainty::default()));
+        assert_eq!(u.bf, 0.0);
+        assert_eq!(u.water_rate, 0.0);
+        assert!(u.bmi > 0.0);
+    }
+}
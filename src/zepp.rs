@@ -0,0 +1,215 @@
+use crate::body::Composition;
+use crate::import::{parse_time, Imported};
+use crate::storage::StoredReading;
+use chrono::{DateTime, NaiveDateTime};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Files of the body composition records in the data export, eg. `BODY/BODY_1681234567890.csv`
+fn is_body_csv(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("BODY") && name.ends_with(".csv"))
+}
+
+/// The `BODY*.csv` files in the directory and its subdirectories, sorted by the path
+fn find_body_csv(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(find_body_csv(&path)?);
+        } else if is_body_csv(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Time of the record: the `timestamp` in seconds (or milliseconds) since the epoch,
+/// or the `time` like `2021-01-31 07:15:00+0000`, as UTC
+fn parse_zepp_time(value: &str) -> Result<NaiveDateTime, Box<dyn std::error::Error>> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<i64>() {
+        let (secs, nsecs) = match timestamp {
+            t if t > 100_000_000_000 => (t / 1000, (t % 1000) as u32 * 1_000_000),
+            t => (t, 0),
+        };
+        return DateTime::from_timestamp(secs, nsecs)
+            .map(|time| time.naive_utc())
+            .ok_or_else(|| format!("invalid timestamp: `{}`", value).into());
+    }
+    match DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%z") {
+        Ok(time) => Ok(time.naive_utc()),
+        Err(_) => parse_time(value),
+    }
+}
+
+/// Read a `BODY` CSV file of the Zepp Life (Mi Fit) data export
+/// (`timestamp`, `weight`, `fatRate`, `bodyWaterRate`, `boneMass`, `metabolism`,
+/// `muscleRate`, `visceralFat`, `impedance`).
+///
+/// The `muscleRate` of Mi Fit is the muscle mass in kg. Empty, `null` and 0 values are unknown.
+pub fn read_csv<R: Read>(reader: R) -> Result<Vec<Imported>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let index = |name: &str| headers.iter().position(|h| h == name);
+    let time = index("timestamp")
+        .or_else(|| index("time"))
+        .ok_or("missing column `timestamp`")?;
+    let [weight, fat, water, bone, metabolism, muscle, visceral_fat, impedance] = [
+        "weight",
+        "fatRate",
+        "bodyWaterRate",
+        "boneMass",
+        "metabolism",
+        "muscleRate",
+        "visceralFat",
+        "impedance",
+    ]
+    .map(index);
+
+    let mut imported = vec![];
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let value = |i: Option<usize>| -> Result<Option<f32>, Box<dyn std::error::Error>> {
+            match i.and_then(|i| record.get(i)) {
+                Some(v) if !v.is_empty() && v != "null" => {
+                    Ok(Some(v.parse::<f32>()?).filter(|v| *v != 0.0))
+                }
+                _ => Ok(None),
+            }
+        };
+        let parse = || -> Result<Option<Imported>, Box<dyn std::error::Error>> {
+            // records without the weight (eg. deleted) are skipped
+            let Some(weight) = value(weight)? else {
+                return Ok(None);
+            };
+            Ok(Some(Imported {
+                profile: None,
+                reading: StoredReading {
+                    date_time: parse_zepp_time(record.get(time).unwrap_or_default())?,
+                    weight,
+                    impedance: value(impedance)?.unwrap_or_default(),
+                    flags: 0,
+                },
+                composition: Composition {
+                    water_rate: value(water)?,
+                    bmr: value(metabolism)?,
+                    visceral_fat: value(visceral_fat)?,
+                    bf: value(fat)?,
+                    muscle_kg: value(muscle)?,
+                    bone_mass: value(bone)?,
+                },
+            }))
+        };
+        if let Some(i) = parse().map_err(|e| format!("line {}: {}", line + 2, e))? {
+            imported.push(i);
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Read the body composition records of the unpacked Zepp Life data export directory
+/// (or of a single `BODY` CSV file)
+pub fn read_export(path: &Path) -> Result<Vec<Imported>, Box<dyn std::error::Error>> {
+    let files = if path.is_dir() {
+        find_body_csv(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    if files.is_empty() {
+        return Err("no BODY*.csv files found".into());
+    }
+
+    let mut imported = vec![];
+    for file in files {
+        let reader = File::open(&file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        imported.extend(read_csv(reader).map_err(|e| format!("{}: {}", file.display(), e))?);
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn time(h: u32, min: u32, s: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 11, 14)
+            .unwrap()
+            .and_hms_opt(h, min, s)
+            .unwrap()
+    }
+
+    #[test]
+    fn zepp_times() {
+        let t = time(22, 13, 20);
+        assert_eq!(parse_zepp_time("1700000000").unwrap(), t);
+        assert_eq!(parse_zepp_time("1700000000000").unwrap(), t);
+        assert_eq!(
+            parse_zepp_time("1700000000500").unwrap(),
+            t + chrono::Duration::milliseconds(500)
+        );
+        assert_eq!(parse_zepp_time("2023-11-15 00:13:20+0200").unwrap(), t);
+        assert_eq!(parse_zepp_time("2023-11-14 22:13:20").unwrap(), t);
+        assert!(parse_zepp_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn read_body_csv() {
+        let file = "\
+timestamp,weight,height,bmi,fatRate,bodyWaterRate,boneMass,metabolism,muscleRate,visceralFat,impedance
+1700000000,80.5,180,24.8,20.1,55.2,3.1,1750,60.2,9,512
+1700003600,null,180,null,null,null,null,null,null,null,null
+1700007200,80.1,180,24.7,0,,3.0,1745,60.0,9,0
+";
+        let imported = read_csv(file.as_bytes()).unwrap();
+        // the record without the weight is skipped
+        assert_eq!(imported.len(), 2);
+
+        let (r, c) = (&imported[0].reading, &imported[0].composition);
+        assert_eq!(r.date_time, time(22, 13, 20));
+        assert_eq!(r.weight, 80.5);
+        assert_eq!(r.impedance, 512.0);
+        assert_eq!(c.bf, Some(20.1));
+        assert_eq!(c.water_rate, Some(55.2));
+        assert_eq!(c.bone_mass, Some(3.1));
+        assert_eq!(c.bmr, Some(1750.0));
+        assert_eq!(c.muscle_kg, Some(60.2));
+        assert_eq!(c.visceral_fat, Some(9.0));
+
+        let (r, c) = (&imported[1].reading, &imported[1].composition);
+        assert_eq!(r.date_time, time(0, 13, 20) + chrono::Duration::days(1));
+        assert_eq!(r.impedance, 0.0);
+        assert_eq!(c.bf, None);
+        assert_eq!(c.water_rate, None);
+    }
+
+    #[test]
+    fn read_errors() {
+        let error = |file: &str| match read_csv(file.as_bytes()) {
+            Ok(_) => panic!("read without an error"),
+            Err(e) => e.to_string(),
+        };
+        assert_eq!(error("weight\n80\n"), "missing column `timestamp`");
+        assert!(error("time,weight\n2023-11-14 22:13:20,80\nnow,80\n").starts_with("line 3:"));
+        assert!(error("timestamp,weight\n1700000000,heavy\n").starts_with("line 2:"));
+    }
+
+    #[test]
+    fn body_csv_names() {
+        assert!(is_body_csv(Path::new("HEALTH/BODY/BODY_1700000000000.csv")));
+        assert!(!is_body_csv(Path::new(
+            "HEALTH/SLEEP/SLEEP_1700000000000.csv"
+        )));
+        assert!(!is_body_csv(Path::new("BODY/BODY_1700000000000.txt")));
+    }
+}